@@ -1,55 +1,471 @@
 use c_str_macro::c_str;
+use log::{debug, info, LevelFilter};
+use sm_ext::executor::{self, CallbackTask};
+use sm_ext::logger::LoggerBuilder;
 use sm_ext::native;
-use sm_ext::types::{cell_t, IPluginContextPtr};
-use sm_ext::{declare_native, register_natives, IExtension, IExtensionInterface, IPluginContext, IShareSys, SMExtension};
+use sm_ext::types::{cell_t, ExecType, HandleAccess, IPluginContextPtr, ResultType, SPArray, SPEnumStruct, SPError, SPRef, SPString, SPStringBuffer, SPVarArgs, TimerFlags, Vector};
+use sm_ext::{declare_native, register_natives, Dispatcher, EnumStruct, GlobalForward, HandleMut, HandleRef, HandleType, IExtension, IExtensionInterface, IForwardManager, IHandleSys, IPluginContext, IPluginFunction, IPluginManager, IRootConsole, IShareSys, ISourceMod, IThreader, ITimerSystem, PluginFunction, PrivateForward, SMExtension, SPEnum, WorkerPool};
+use std::cell::RefCell;
 use std::error::Error;
 use std::ffi::{CStr, CString};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SPEnum)]
+#[repr(i32)]
+pub enum Team {
+    Unassigned = 0,
+    Spectator = 1,
+    Red = 2,
+    Blue = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SPEnum)]
+pub struct DamageFlags(u32);
+
+impl DamageFlags {
+    pub const CRIT: DamageFlags = DamageFlags(1 << 0);
+    pub const HEADSHOT: DamageFlags = DamageFlags(1 << 1);
+
+    pub fn contains(self, other: DamageFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+#[derive(Debug, Clone, EnumStruct)]
+pub struct PlayerInfo {
+    pub team: i32,
+    pub health: f32,
+    pub alive: bool,
+    pub name: [u8; 32],
+    pub position: [f32; 3],
+}
+
 #[derive(SMExtension)]
 #[extension(name = "Rusty", description = "Sample extension written in Rust")]
 pub struct MyExtension();
 
 unsafe extern "C" fn test_native(ctx: IPluginContextPtr, args: *const cell_t) -> cell_t {
-    println!(">>> {:?} {:?}", ctx, args);
+    debug!("{:?} {:?}", ctx, args);
 
     47.into()
 }
 
 declare_native!(
     fn test_native2(ctx: &IPluginContext, args: &[cell_t]) -> cell_t {
-        println!(">>> {:?} {:?}", ctx, args);
+        debug!("{:?} {:?}", ctx, args);
 
         0.into()
     }
 );
 
 #[native]
-fn test_native3(ctx: &IPluginContext, a: i32, b: i32, c: f32, d: &CStr, e: &mut i32, f: &mut f32) -> Result<f32, String> {
-    println!(">>> {:?} {:?} {:?} {:?} {:?} {:?} {:?}", ctx, a, b, c, d, e, f);
+fn test_native3(ctx: &IPluginContext, a: i32, b: i32, c: f32, d: &CStr, mut e: SPRef<i32>, mut f: SPRef<f32>) -> Result<f32, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?} {:?} {:?} {:?} {:?}", ctx, a, b, c, d, e.get()?, f.get()?);
 
-    *e = 47;
-    *f = 1.5;
+    e.set(47)?;
+    f.set(1.5)?;
 
     Ok(5.0)
 }
 
 #[native]
 fn test_native4(ctx: &IPluginContext) -> Result<i32, Box<dyn Error>> {
-    println!(">>> {:?}", ctx);
+    debug!("{:?}", ctx);
 
     Err("This is an error...".into())
 }
 
+#[native]
+fn test_native5(ctx: &IPluginContext, #[size(maxlength)] mut buffer: SPStringBuffer, maxlength: i32) -> Result<i32, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?}", ctx, buffer, maxlength);
+
+    let written = buffer.write("Hello from Rust")?;
+
+    Ok(written as i32)
+}
+
+#[native]
+fn test_native6(ctx: &IPluginContext, #[size(count)] values: &[i32], count: i32, #[size(3)] out: Option<&mut [f32]>) -> Result<i32, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?} {:?}", ctx, values, count, out);
+
+    if let Some(out) = out {
+        out.copy_from_slice(&[1.0, 2.0, 3.0]);
+    }
+
+    Ok(values.iter().sum())
+}
+
+#[native]
+fn test_native7(ctx: &IPluginContext, a: i32, b: Option<f32>, #[default = 10] c: i32) -> Result<f32, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?} {:?}", ctx, a, b, c);
+
+    Ok(a as f32 + b.unwrap_or(0.5) * c as f32)
+}
+
+#[native]
+fn test_native8(ctx: &IPluginContext, format: &CStr, rest: SPVarArgs) -> Result<i32, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?}", ctx, format, rest);
+
+    for (i, arg) in rest.iter().enumerate() {
+        match format.to_bytes().get(i) {
+            Some(b'd') => debug!("{}: {}", i, arg.as_int()?),
+            Some(b'f') => debug!("{}: {}", i, arg.as_float()?),
+            Some(b's') => debug!("{}: {}", i, arg.as_str()?),
+            Some(b'a') => debug!("{}: {:?}", i, arg.as_array::<i32>(3)?),
+            _ => return Err(format!("Unexpected argument {}", i).into()),
+        }
+    }
+
+    Ok(rest.len() as i32)
+}
+
+#[native]
+fn test_native9(ctx: &IPluginContext, name: SPString, #[size(count)] mut values: SPArray<f32>, count: i32) -> Result<f32, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?} {:?}", ctx, name.get()?, values.to_vec()?, count);
+
+    let mut sum = 0.0;
+    for i in 0..values.len() {
+        sum += values.get(i)?;
+        values.set(i, sum)?;
+    }
+
+    Ok(sum)
+}
+
+#[native]
+fn test_native10(ctx: &IPluginContext, flags: u8, index: usize, letter: char) -> Result<bool, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?} {:?}", ctx, flags, index, letter);
+
+    Ok(flags & 1 != 0 && index < 64 && letter.is_ascii_alphabetic())
+}
+
+#[native]
+fn test_native11(ctx: &IPluginContext, value: i64) -> Result<(), Box<dyn Error>> {
+    debug!("{:?} {:?}", ctx, value);
+
+    Ok(())
+}
+
+#[native]
+fn test_native12(ctx: &IPluginContext, team: Team, flags: DamageFlags) -> Result<Team, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?}", ctx, team, flags);
+
+    Ok(match team {
+        Team::Red if flags.contains(DamageFlags::CRIT) => Team::Blue,
+        Team::Blue if flags.contains(DamageFlags::HEADSHOT) => Team::Red,
+        team => team,
+    })
+}
+
+#[native]
+fn test_native13(ctx: &IPluginContext, mut info: SPEnumStruct<PlayerInfo>) -> Result<bool, Box<dyn Error>> {
+    let mut value = info.get()?;
+    debug!("{:?} {:?} {:?}", ctx, value, CStr::from_bytes_until_nul(&value.name));
+
+    value.health -= 10.0;
+    value.alive = value.health > 0.0;
+    info.set(&value)?;
+
+    Ok(value.alive)
+}
+
+#[native]
+fn test_native14(ctx: &IPluginContext, mut velocity: SPEnumStruct<Vector>, name: Option<&CStr>, origin: Option<Vector>) -> Result<bool, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?} {:?}", ctx, velocity.get()?, name, origin);
+
+    if let Some(origin) = origin {
+        velocity.set(&Vector::new(-origin.x, -origin.y, -origin.z))?;
+    }
+
+    Ok(name.is_some())
+}
+
+#[native]
+fn test_native15(ctx: &IPluginContext, callback: PluginFunction, value: i32) -> Result<i32, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?} {:?}", ctx, callback, callback.get().map(|callback| callback.debug_name()), value);
+
+    let ret = call_test_callback(&callback, value)?;
+
+    // Called again with the next value on every map end, until the plugin is unloaded.
+    LAST_CALLBACK.with(|last| *last.borrow_mut() = Some((callback, value + 1)));
+
+    Ok(ret.into())
+}
+
+fn call_test_callback(callback: &PluginFunction, value: i32) -> Result<cell_t, SPError> {
+    let mut result = [0.into(); 2];
+    let mut buffer = [0u8; 64];
+    let ret = callback.prepare_call()?.push_cell(value)?.push_string(c_str!("Hello from Rust"))?.push_array_mut(&mut result)?.push_string_buffer(&mut buffer)?.execute()?;
+
+    debug!("{:?} {:?} {:?}", ret, result, CStr::from_bytes_until_nul(&buffer));
+
+    Ok(ret)
+}
+
+thread_local! {
+    static LAST_CALLBACK: RefCell<Option<(PluginFunction, i32)>> = const { RefCell::new(None) };
+}
+
+thread_local! {
+    static ON_RUST_EVENT: RefCell<Option<GlobalForward<(i32, &'static CStr)>>> = const { RefCell::new(None) };
+    static RUST_EVENT_HOOKS: RefCell<Option<PrivateForward<(i32, &'static CStr)>>> = const { RefCell::new(None) };
+}
+
+#[native]
+fn test_native16(ctx: &IPluginContext, client: i32, name: &CStr) -> Result<i32, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?}", ctx, client, name);
+
+    let result = ON_RUST_EVENT.with(|forward| match &*forward.borrow() {
+        Some(forward) => forward.fire((client, name)),
+        None => Ok(0.into()),
+    })?;
+
+    let hook_result = RUST_EVENT_HOOKS.with(|forward| match &*forward.borrow() {
+        Some(forward) => forward.fire((client, name)),
+        None => Ok(0.into()),
+    })?;
+
+    Ok(i32::from(result).max(hook_result.into()))
+}
+
+#[native]
+fn test_native17(ctx: &IPluginContext, callback: IPluginFunction, hook: bool) -> Result<bool, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?}", ctx, callback, hook);
+
+    let changed = RUST_EVENT_HOOKS.with(|forward| match &*forward.borrow() {
+        Some(forward) if hook => forward.add_function(&callback),
+        Some(forward) => forward.remove_function(&callback),
+        None => false,
+    });
+
+    Ok(changed)
+}
+
+#[derive(Debug)]
+pub struct Counter {
+    pub value: i32,
+    pub history: Vec<i32>,
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        debug!("Counter dropped at {:?}", self.value);
+    }
+}
+
+thread_local! {
+    static COUNTER_TYPE: RefCell<Option<HandleType<Counter>>> = const { RefCell::new(None) };
+}
+
+#[native]
+fn test_native18(ctx: &IPluginContext, start: i32) -> Result<cell_t, Box<dyn Error>> {
+    debug!("{:?} {:?}", ctx, start);
+
+    let handle = COUNTER_TYPE.with(|handle_type| match &*handle_type.borrow() {
+        Some(handle_type) => handle_type.create_handle(Counter { value: start, history: Vec::new() }, ctx.get_identity()).map_err(|e| e.to_string()),
+        None => Err("Counter handle type was not created".to_string()),
+    })?;
+
+    Ok(handle)
+}
+
+#[native]
+fn test_native19(ctx: &IPluginContext, mut counter: HandleMut<Counter>, amount: i32) -> Result<i32, Box<dyn Error>> {
+    debug!("{:?} {:x} {:?} {:?}", ctx, counter.handle(), *counter, amount);
+
+    counter.value += amount;
+    counter.history.push(amount);
+
+    Ok(counter.value)
+}
+
+#[native]
+fn test_native20(ctx: &IPluginContext, a: HandleRef<Counter>, b: HandleRef<Counter>) -> Result<bool, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?}", ctx, *a, *b);
+
+    Ok(a.value == b.value)
+}
+
+#[native]
+fn test_native21(ctx: &IPluginContext, counter: HandleRef<Counter>, close: bool) -> Result<cell_t, Box<dyn Error>> {
+    debug!("{:?} {:x} {:?}", ctx, counter.handle(), close);
+
+    let handle = COUNTER_TYPE.with(|handle_type| match &*handle_type.borrow() {
+        Some(handle_type) if close => handle_type.free_handle(counter.handle(), ctx.get_identity()).map(|_| 0.into()).map_err(|e| e.to_string()),
+        Some(handle_type) => handle_type.clone_handle(counter.handle(), ctx.get_identity(), ctx.get_identity()).map_err(|e| e.to_string()),
+        None => Err("Counter handle type was not created".to_string()),
+    })?;
+
+    Ok(handle)
+}
+
+thread_local! {
+    static TIMERS: RefCell<Option<ITimerSystem>> = const { RefCell::new(None) };
+}
+
+#[native]
+fn test_native22(ctx: &IPluginContext, interval: f32, ticks: i32) -> Result<(), Box<dyn Error>> {
+    debug!("{:?} {:?} {:?}", ctx, interval, ticks);
+
+    let mut remaining = ticks;
+    let timer = TIMERS.with(|timers| match &*timers.borrow() {
+        Some(timers) => timers.create_timer(interval, TimerFlags::REPEAT | TimerFlags::NO_MAPCHANGE, move || {
+            remaining -= 1;
+            debug!("Timer tick, {:?} remaining", remaining);
+
+            if remaining <= 0 {
+                return ResultType::STOP;
+            }
+
+            ResultType::CONTINUE
+        }),
+        None => Err("Timer system is not available"),
+    })?;
+
+    timer.detach();
+
+    Ok(())
+}
+
+thread_local! {
+    static DISPATCHER: RefCell<Option<Dispatcher>> = const { RefCell::new(None) };
+}
+
+#[native]
+fn test_native23(ctx: &IPluginContext, value: i32) -> Result<(), Box<dyn Error>> {
+    debug!("{:?} {:?}", ctx, value);
+
+    let dispatcher = DISPATCHER.with(|dispatcher| dispatcher.borrow().clone()).ok_or("Dispatcher is not available")?;
+
+    std::thread::spawn(move || {
+        let result = (1..=value.max(0) as u64).product::<u64>();
+
+        let _ = dispatcher.dispatch(move || {
+            debug!("Back on the main thread with {:?}", result);
+        });
+    });
+
+    Ok(())
+}
+
+#[native]
+fn test_native24(ctx: &IPluginContext, callback: PluginFunction, seconds: f32, value: i32) -> Result<CallbackTask, Box<dyn Error>> {
+    debug!("{:?} {:?} {:?} {:?}", ctx, callback, seconds, value);
+
+    Ok(CallbackTask::new(callback, async move {
+        executor::sleep(seconds).await;
+
+        (value * 2,)
+    }))
+}
+
+thread_local! {
+    static WORKERS: RefCell<Option<WorkerPool>> = const { RefCell::new(None) };
+}
+
+#[native]
+fn test_native25(ctx: &IPluginContext, count: i32) -> Result<i32, Box<dyn Error>> {
+    debug!("{:?} {:?}", ctx, count);
+
+    WORKERS.with(|workers| {
+        let workers = workers.borrow();
+        let workers = workers.as_ref().ok_or("Worker pool is not available")?;
+
+        for i in 0..count.max(0) {
+            workers.submit(move || (0..=i64::from(i) * 1_000_000).sum::<i64>(), move |sum| debug!("Job {:?} finished with {:?}", i, sum))?;
+        }
+
+        Ok(workers.pending() as i32)
+    })
+}
+
 impl IExtensionInterface for MyExtension {
     fn on_extension_load(&mut self, myself: IExtension, sys: IShareSys, late: bool) -> Result<(), CString> {
-        println!(">>> Rusty extension loaded! me = {:?}, sys = {:?}, late = {:?}", myself, sys, late);
+        let sourcemod: ISourceMod = sys.request_typed_interface(&myself).map_err(|_| c_str!("Failed to get ISourceMod"))?;
+        let console: IRootConsole = sys.request_typed_interface(&myself).map_err(|_| c_str!("Failed to get IRootConsole"))?;
+        LoggerBuilder::new(&sourcemod, &console, &myself).level(LevelFilter::Debug).log_file(true).install().map_err(|_| c_str!("Failed to install logger"))?;
+
+        info!("Rusty extension loaded! me = {:?}, sys = {:?}, late = {:?}", myself, sys, late);
 
         let smutils = sys.request_interface(&myself, "ISourceMod", 14).map_err(|_| c_str!("Failed to get ISourceMod"))?;
 
-        println!(">>> Got interface: {:?} v{:?}", smutils.get_interface_name().unwrap(), smutils.get_interface_version());
+        debug!("Got interface: {:?} v{:?}", smutils.get_interface_name().unwrap(), smutils.get_interface_version());
+        debug!("Game folder: {:?}", sourcemod.get_game_folder_name());
+        DISPATCHER.with(|d| *d.borrow_mut() = Some(sourcemod.dispatcher()));
 
-        register_natives!(&sys, &myself, [("Rust_Test", test_native), ("Rust_Test2", test_native2), ("Rust_Test3", __test_native3_adapter), ("Rust_Test4", __test_native4_adapter),]);
+        let threader: IThreader = sys.request_typed_interface(&myself).map_err(|_| c_str!("Failed to get IThreader"))?;
+        WORKERS.with(|w| *w.borrow_mut() = Some(threader.create_worker_pool(&sourcemod, 4)));
+
+        let forwards: IForwardManager = sys.request_typed_interface(&myself).map_err(|_| c_str!("Failed to get IForwardManager"))?;
+        let forward = forwards.create_global_forward(c_str!("OnRustEvent"), ExecType::EVENT).map_err(|_| c_str!("Failed to create OnRustEvent forward"))?;
+        ON_RUST_EVENT.with(|f| *f.borrow_mut() = Some(forward));
+
+        let plugins: IPluginManager = sys.request_typed_interface(&myself).map_err(|_| c_str!("Failed to get IPluginManager"))?;
+        plugins.listen_for_unloaded_plugins();
+        let hooks = forwards.create_private_forward(&plugins, ExecType::HOOK).map_err(|_| c_str!("Failed to create private forward"))?;
+        RUST_EVENT_HOOKS.with(|f| *f.borrow_mut() = Some(hooks));
+
+        let timers: ITimerSystem = sys.request_typed_interface(&myself).map_err(|_| c_str!("Failed to get ITimerSystem"))?;
+        executor::start(&sourcemod, &timers, &plugins);
+        TIMERS.with(|t| *t.borrow_mut() = Some(timers));
+
+        let handles: IHandleSys = sys.request_typed_interface(&myself).map_err(|_| c_str!("Failed to get IHandleSys"))?;
+        // Plugins can't clone counters themselves, only through our natives.
+        let (_, mut access) = handles.init_access_defaults();
+        access.access[HandleAccess::CLONE] |= HandleAccess::RESTRICT_IDENTITY;
+        let mut counter_type: HandleType<Counter> = handles.create_type_with_access(c_str!("RustCounter"), myself.get_identity(), &access).map_err(|_| c_str!("Failed to create RustCounter handle type"))?;
+        counter_type.set_approx_size(|counter| std::mem::size_of_val(counter) + counter.history.capacity() * std::mem::size_of::<i32>());
+        COUNTER_TYPE.with(|t| *t.borrow_mut() = Some(counter_type));
+
+        register_natives!(
+            &sys,
+            &myself,
+            [
+                ("Rust_Test", test_native),
+                ("Rust_Test2", test_native2),
+                ("Rust_Test3", __test_native3_adapter),
+                ("Rust_Test4", __test_native4_adapter),
+                ("Rust_Test5", __test_native5_adapter),
+                ("Rust_Test6", __test_native6_adapter),
+                ("Rust_Test7", __test_native7_adapter),
+                ("Rust_Test8", __test_native8_adapter),
+                ("Rust_Test9", __test_native9_adapter),
+                ("Rust_Test10", __test_native10_adapter),
+                ("Rust_Test11", __test_native11_adapter),
+                ("Rust_Test12", __test_native12_adapter),
+                ("Rust_Test13", __test_native13_adapter),
+                ("Rust_Test14", __test_native14_adapter),
+                ("Rust_Test15", __test_native15_adapter),
+                ("Rust_Test16", __test_native16_adapter),
+                ("Rust_Test17", __test_native17_adapter),
+                ("Rust_Test18", __test_native18_adapter),
+                ("Rust_Test19", __test_native19_adapter),
+                ("Rust_Test20", __test_native20_adapter),
+                ("Rust_Test21", __test_native21_adapter),
+                ("Rust_Test22", __test_native22_adapter),
+                ("Rust_Test23", __test_native23_adapter),
+                ("Rust_Test24", __test_native24_adapter),
+                ("Rust_Test25", __test_native25_adapter),
+            ]
+        );
 
         Ok(())
     }
+
+    fn on_core_map_end(&mut self) {
+        if let Some((callback, value)) = LAST_CALLBACK.with(|last| last.borrow_mut().take()) {
+            match call_test_callback(&callback, value) {
+                Ok(_) => LAST_CALLBACK.with(|last| *last.borrow_mut() = Some((callback, value + 1))),
+                Err(e) => debug!("Stored callback is gone: {}", e),
+            }
+        }
+    }
+
+    fn on_extension_unload(&mut self) {
+        LAST_CALLBACK.with(|last| last.borrow_mut().take());
+        ON_RUST_EVENT.with(|f| f.borrow_mut().take());
+        RUST_EVENT_HOOKS.with(|f| f.borrow_mut().take());
+        COUNTER_TYPE.with(|t| t.borrow_mut().take());
+        DISPATCHER.with(|d| d.borrow_mut().take());
+        WORKERS.with(|w| w.borrow_mut().take());
+    }
 }
@@ -1,7 +1,6 @@
 extern crate proc_macro;
 use proc_macro2::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
 use quote::{format_ident, quote, quote_spanned, ToTokens, TokenStreamExt};
-use syn;
 use syn::spanned::Spanned;
 
 #[proc_macro_derive(SMExtension, attributes(extension))]
@@ -211,9 +210,148 @@ impl MetadataInput {
     }
 }
 
+#[proc_macro_derive(SPEnum)]
+pub fn derive_sp_enum(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+    let name = &ast.ident;
+
+    let expanded = match &ast.data {
+        // Fieldless enums convert from their discriminant, rejecting any value without a variant.
+        syn::Data::Enum(data) => {
+            if let Some(variant) = data.variants.iter().find(|variant| !variant.fields.is_empty()) {
+                let span = variant.span();
+                return error("SPEnum variants must not have any fields", span, span).into();
+            }
+
+            // Plugin enums are cells, so the discriminants must all fit in one.
+            let repr_i32 = ast.attrs.iter().filter(|attr| attr.path.is_ident("repr")).any(|attr| match attr.parse_args_with(syn::punctuated::Punctuated::<Ident, syn::Token![,]>::parse_terminated) {
+                Ok(reprs) => reprs.iter().any(|repr| repr == "i32"),
+                Err(_) => false,
+            });
+            if !repr_i32 {
+                let span = ast.ident.span();
+                return error("SPEnum enums must be #[repr(i32)]", span, span).into();
+            }
+
+            let variants: Vec<_> = data.variants.iter().map(|variant| &variant.ident).collect();
+
+            quote! {
+                #(
+                    const _: () = assert!(#name::#variants as i128 == #name::#variants as i32 as i128, "SPEnum discriminant does not fit in a cell");
+                )*
+
+                impl ::std::convert::TryFrom<::sm_ext::types::cell_t> for #name {
+                    type Error = String;
+
+                    fn try_from(value: ::sm_ext::types::cell_t) -> Result<Self, Self::Error> {
+                        let value = i32::from(value);
+                        #(
+                            if value == #name::#variants as i32 {
+                                return Ok(#name::#variants);
+                            }
+                        )*
+
+                        Err(format!("Invalid {} value: {}", stringify!(#name), value))
+                    }
+                }
+
+                impl From<#name> for ::sm_ext::types::cell_t {
+                    fn from(value: #name) -> Self {
+                        ::sm_ext::types::cell_t::from(value as i32)
+                    }
+                }
+            }
+        }
+
+        // Flag structs wrap a single integer, which holds the bits of the cell as-is.
+        syn::Data::Struct(data) if data.fields.len() == 1 => {
+            let field = data.fields.iter().next().unwrap();
+            let ty = &field.ty;
+            let (member, construct) = match &field.ident {
+                Some(ident) => (quote!(#ident), quote!(#name { #ident: i32::from(value) as #ty })),
+                None => (quote!(0), quote!(#name(i32::from(value) as #ty))),
+            };
+
+            quote! {
+                impl From<::sm_ext::types::cell_t> for #name {
+                    #[allow(clippy::unnecessary_cast)]
+                    fn from(value: ::sm_ext::types::cell_t) -> Self {
+                        #construct
+                    }
+                }
+
+                impl From<#name> for ::sm_ext::types::cell_t {
+                    #[allow(clippy::unnecessary_cast)]
+                    fn from(value: #name) -> Self {
+                        ::sm_ext::types::cell_t::from(value.#member as i32)
+                    }
+                }
+            }
+        }
+
+        _ => {
+            let span = ast.span();
+            return error("SPEnum must be derived for a fieldless enum or a struct with a single integer field", span, span).into();
+        }
+    };
+
+    expanded.into()
+}
+
+#[proc_macro_derive(EnumStruct)]
+pub fn derive_enum_struct(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+    let name = &ast.ident;
+
+    let fields = match &ast.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => {
+            let span = ast.span();
+            return error("EnumStruct must be derived for a struct", span, span).into();
+        }
+    };
+
+    // Fields are laid out one after another in declaration order, as SourcePawn does.
+    let mut offset = quote!(0);
+    let mut reads = Vec::new();
+    let mut writes = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let ty = &field.ty;
+        let member = match &field.ident {
+            Some(ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(syn::Index::from(i)),
+        };
+
+        let end = quote!(#offset + <#ty as ::sm_ext::types::EnumStructField>::CELLS);
+        reads.push(quote_spanned!(field.span() => #member: <#ty as ::sm_ext::types::EnumStructField>::read_cells(&cells[#offset..#end])));
+        writes.push(quote_spanned!(field.span() => ::sm_ext::types::EnumStructField::write_cells(&self.#member, &mut cells[#offset..#end]);));
+        offset = end;
+    }
+
+    let expanded = quote! {
+        impl ::sm_ext::types::EnumStructField for #name {
+            const CELLS: usize = #offset;
+
+            #[allow(clippy::identity_op)]
+            fn read_cells(cells: &[::sm_ext::types::cell_t]) -> Self {
+                assert_eq!(cells.len(), Self::CELLS);
+                #name { #(#reads),* }
+            }
+
+            #[allow(clippy::identity_op)]
+            fn write_cells(&self, cells: &mut [::sm_ext::types::cell_t]) {
+                assert_eq!(cells.len(), Self::CELLS);
+                #(#writes)*
+            }
+        }
+    };
+
+    expanded.into()
+}
+
 #[proc_macro_attribute]
 pub fn native(_attr: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let input = syn::parse_macro_input!(item as syn::ItemFn);
+    let mut input = syn::parse_macro_input!(item as syn::ItemFn);
     // println!("{:#?}", input);
 
     let mut output = TokenStream::new();
@@ -238,9 +376,24 @@ pub fn native(_attr: proc_macro::TokenStream, item: proc_macro::TokenStream) ->
         output.extend(error("Native callback must not have any generic parameters", span, span));
     }
 
+    // Parameters are matched to plugin arguments by position, the first one being the plugin context.
+    let mut param_names = Vec::new();
+    let mut param_optional = Vec::new();
+    for param in &input.sig.inputs {
+        if let syn::FnArg::Typed(param) = param {
+            param_names.push(match &*param.pat {
+                syn::Pat::Ident(pat) => Some(pat.ident.clone()),
+                _ => None,
+            });
+            param_optional.push(option_inner_type(&param.ty).is_some() || is_var_args(&param.ty) || param.attrs.iter().any(|attr| attr.path.is_ident("default")));
+        }
+    }
+
+    let (args_minimum, misplaced_params) = native_args_minimum(&param_optional);
+
     let mut param_count: i32 = 0;
     let mut param_output = TokenStream::new();
-    for param in &input.sig.inputs {
+    for param in &mut input.sig.inputs {
         match param {
             syn::FnArg::Receiver(param) => {
                 let span = param.span();
@@ -253,13 +406,112 @@ pub fn native(_attr: proc_macro::TokenStream, item: proc_macro::TokenStream) ->
                     continue;
                 }
 
+                let mut size = None;
+                let mut default = None;
+                let mut attr_error = TokenStream::new();
+                param.attrs.retain(|attr| {
+                    if attr.path.is_ident("size") {
+                        match attr.parse_args::<syn::Expr>() {
+                            Ok(expr) => size = Some(expr),
+                            Err(e) => attr_error.extend(e.to_compile_error()),
+                        }
+                    } else if attr.path.is_ident("default") {
+                        match syn::parse::Parser::parse2(parse_default_attr, attr.tokens.clone()) {
+                            Ok(expr) => default = Some(expr),
+                            Err(e) => attr_error.extend(e.to_compile_error()),
+                        }
+                    } else {
+                        return true;
+                    }
+
+                    false
+                });
+                output.extend(attr_error);
+
                 let param_idx = (param_count - 1) as isize;
-                param_output.extend(quote_spanned!(param.span() => (*(args.offset(#param_idx))).try_into_plugin(&ctx)?,));
+                let value = quote_spanned!(param.span() => *(args.offset(#param_idx)));
+
+                let optional = param_optional[param_idx as usize];
+                if misplaced_params.contains(&(param_idx as usize)) {
+                    let span = param.span();
+                    output.extend(error("Native parameter must be optional, as it follows an optional parameter", span, span));
+                }
+
+                if default.is_some() && option_inner_type(&param.ty).is_some() {
+                    let span = param.ty.span();
+                    output.extend(error("Native parameter with a default value must not be an Option", span, span));
+                }
+
+                // Variadic arguments take up all of the remaining plugin arguments.
+                if is_var_args(&param.ty) {
+                    if param_count as usize != param_names.len() {
+                        let span = param.span();
+                        output.extend(error("Native variadic parameter must be the last parameter", span, span));
+                    }
+
+                    if size.is_some() || default.is_some() {
+                        let span = param.span();
+                        output.extend(error("Native variadic parameter must not have a size or default value", span, span));
+                    }
+
+                    let param_idx = param_idx as i32;
+                    param_output.extend(quote_spanned!(param.span() => sm_ext::types::SPVarArgs::new(&ctx, std::slice::from_raw_parts(args.offset(#param_idx as isize), (count + 1 - #param_idx).max(0) as usize)),));
+                    continue;
+                }
+
+                // The highest argument index this parameter reads, which must be present for it to be converted.
+                let mut last_idx = param_idx;
+
+                // Sized parameters (buffers and arrays) get their length from another argument, or a fixed value.
+                let value = match size {
+                    Some(syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. })) => match lit.base10_parse::<i32>() {
+                        Ok(size) => quote_spanned!(lit.span() => sm_ext::types::SizedArg { value: #value, size: sm_ext::types::cell_t::from(#size) }),
+                        Err(e) => {
+                            output.extend(e.to_compile_error());
+                            value
+                        }
+                    },
+                    Some(syn::Expr::Path(path)) => match param_names.iter().position(|name| name.as_ref().is_some_and(|name| path.path.is_ident(name))) {
+                        Some(idx) if idx > 0 => {
+                            if param_optional[idx] && !optional {
+                                let span = path.span();
+                                output.extend(error("Native parameter size must not be optional, as the parameter is required", span, span));
+                            }
+
+                            let size_idx = idx as isize;
+                            last_idx = last_idx.max(size_idx);
+                            quote_spanned!(path.span() => sm_ext::types::SizedArg { value: #value, size: *(args.offset(#size_idx)) })
+                        }
+                        _ => {
+                            let span = path.span();
+                            output.extend(error("Native parameter size must name another parameter", span, span));
+                            value
+                        }
+                    },
+                    Some(expr) => {
+                        let span = expr.span();
+                        output.extend(error("Native parameter size must be a parameter name or an integer literal", span, span));
+                        value
+                    }
+                    None => value,
+                };
+
+                let last_idx = last_idx as i32;
+                let value = match (option_inner_type(&param.ty), default) {
+                    // Types that the plugin can pass a null value for are None then too, see `sm_ext::types::NullableArg`.
+                    (Some(inner), _) => {
+                        let value = quote_spanned!(param.span() => (&sm_ext::types::OptionalArg::<#inner, _>(#value, std::marker::PhantomData)).try_from_optional_arg(&ctx)?);
+                        quote_spanned!(param.span() => if count >= #last_idx { #value } else { None })
+                    }
+                    (None, Some(default)) => quote_spanned!(param.span() => if count >= #last_idx { (#value).try_into_plugin(&ctx)? } else { #default }),
+                    _ => quote_spanned!(param.span() => (#value).try_into_plugin(&ctx)?),
+                };
+
+                param_output.extend(quote_spanned!(param.span() => #value,));
             }
         };
     }
 
-    let args_minimum = param_count - 1; // TODO: Handle optional args.
     let callback_ident = &input.sig.ident;
     let wrapper_ident = format_ident!("__{}_adapter", callback_ident);
     output.extend(quote! {
@@ -268,7 +520,7 @@ pub fn native(_attr: proc_macro::TokenStream, item: proc_macro::TokenStream) ->
 
             sm_ext::safe_native_invoke(&ctx, || -> Result<cell_t, Box<dyn std::error::Error>> {
                 use std::convert::TryInto;
-                use sm_ext::types::TryIntoWithContext;
+                use sm_ext::types::{TryFromOptionalArg, TryIntoWithContext};
 
                 let count: i32 = (*args).into();
                 if count < #args_minimum {
@@ -293,12 +545,21 @@ pub fn native(_attr: proc_macro::TokenStream, item: proc_macro::TokenStream) ->
 
 #[proc_macro_attribute]
 pub fn vtable(attr: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let this_ptr_type = syn::parse_macro_input!(attr as syn::Path);
+    let args = syn::parse_macro_input!(attr as VtableArgs);
+    let this_ptr_type = &args.this_ptr_type;
     let mut input = syn::parse_macro_input!(item as syn::ItemStruct);
     let mut output = TokenStream::new();
 
     // println!("{}", input.to_token_stream().to_string());
 
+    if let Some(wrapper) = &args.wrapper {
+        output.extend(vtable_wrapper(&input, this_ptr_type, wrapper));
+    }
+
+    for field in &mut input.fields {
+        field.attrs.retain(|attr| !attr.path.is_ident("wrapper"));
+    }
+
     input.attrs.push(syn::parse_quote!(#[repr(C)]));
 
     for field in &mut input.fields {
@@ -307,34 +568,404 @@ pub fn vtable(attr: proc_macro::TokenStream, item: proc_macro::TokenStream) -> p
 
             // TODO: The dummy arg needs inserting for doing fastcall on Windows.
             ty.inputs.insert(0, syn::parse_quote!(this: #this_ptr_type));
-
-            // TODO: This depends on the platform (once we move away from thiscall) and whether there are varargs
-            ty.abi = Some(match ty.variadic {
-                Some(_) => syn::parse_quote!(extern "cdecl"),
-                None => syn::parse_quote!(extern "thiscall"),
-            });
         } else {
             let span = field.span();
             output.extend(error("All vtable struct fields must be bare functions", span, span));
         }
     }
 
-    output.extend(input.to_token_stream());
+    // The struct is emitted once per platform ABI, as the calling convention is part of the function pointer type.
+    for (cfg, abi) in member_function_abis() {
+        let mut input = input.clone();
+        input.attrs.push(cfg);
+
+        for field in &mut input.fields {
+            if let syn::Type::BareFn(ty) = &mut field.ty {
+                ty.abi = Some(match ty.variadic {
+                    Some(_) => syn::parse_quote!(extern "C"),
+                    None => abi.clone(),
+                });
+            }
+        }
+
+        output.extend(input.to_token_stream());
+    }
 
     // println!("{}", output.to_string());
 
     output.into()
 }
 
+struct VtableArgs {
+    this_ptr_type: syn::Path,
+    wrapper: Option<Ident>,
+}
+
+impl syn::parse::Parse for VtableArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let this_ptr_type = input.parse()?;
+        let mut wrapper = None;
+
+        while !input.is_empty() {
+            input.parse::<syn::Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let key: Ident = input.parse()?;
+            input.parse::<syn::Token![=]>()?;
+            if key == "wrapper" {
+                wrapper = Some(input.parse()?);
+            } else {
+                return Err(syn::Error::new(key.span(), format!("unsupported vtable argument '{}'", key)));
+            }
+        }
+
+        Ok(VtableArgs { this_ptr_type, wrapper })
+    }
+}
+
+// Generates a newtype around the interface pointer with a safe method for each public, non-variadic vtable slot.
+// Slots that need a hand-written wrapper can be excluded with #[wrapper(skip)].
+fn vtable_wrapper(input: &syn::ItemStruct, this_ptr_type: &syn::Path, wrapper: &Ident) -> TokenStream {
+    let mut output = TokenStream::new();
+    let mut methods = Vec::new();
+
+    for field in &input.fields {
+        let ty = match &field.ty {
+            syn::Type::BareFn(ty) => ty,
+            _ => continue,
+        };
+
+        if ty.variadic.is_some() {
+            continue;
+        }
+
+        if let syn::Visibility::Inherited = field.vis {
+            continue;
+        }
+
+        let mut skip = false;
+        for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("wrapper")) {
+            match attr.parse_args::<Ident>() {
+                Ok(ident) if ident == "skip" => skip = true,
+                _ => {
+                    let span = attr.span();
+                    output.extend(error("Expected #[wrapper(skip)]", span, span));
+                }
+            }
+        }
+
+        if skip {
+            continue;
+        }
+
+        let slot = match &field.ident {
+            Some(ident) => ident,
+            None => continue,
+        };
+
+        let method_ident = format_ident!("{}", pascal_to_snake_case(&slot.to_string()), span = slot.span());
+
+        let mut is_unsafe = false;
+        let mut params = Vec::new();
+        let mut args = Vec::new();
+        for (idx, input) in ty.inputs.iter().enumerate() {
+            let name = match &input.name {
+                Some((name, _)) => name.clone(),
+                None => format_ident!("arg{}", idx),
+            };
+
+            if is_c_string(&input.ty) {
+                params.push(quote!(#name: &::std::ffi::CStr));
+                args.push(quote!(#name.as_ptr()));
+            } else {
+                if is_pointer(&input.ty) {
+                    is_unsafe = true;
+                }
+
+                let ty = &input.ty;
+                params.push(quote!(#name: #ty));
+                args.push(quote!(#name));
+            }
+        }
+
+        let call = quote!(((**self.0).#slot)(self.0, #(#args),*));
+        let (output_type, body) = match &ty.output {
+            syn::ReturnType::Type(_, ret) if is_c_string(ret) => (
+                quote!(-> Option<&::std::ffi::CStr>),
+                quote! {
+                    let ptr = #call;
+                    if ptr.is_null() {
+                        None
+                    } else {
+                        Some(::std::ffi::CStr::from_ptr(ptr))
+                    }
+                },
+            ),
+            syn::ReturnType::Type(_, ret) if is_unit(ret) => (quote!(), call),
+            syn::ReturnType::Type(_, ret) => (quote!(-> #ret), call),
+            syn::ReturnType::Default => (quote!(), call),
+        };
+
+        let cfgs = field.attrs.iter().filter(|attr| attr.path.is_ident("cfg"));
+        methods.push(if is_unsafe {
+            quote! {
+                #(#cfgs)*
+                /// # Safety
+                ///
+                /// Pointer arguments are passed through to SourceMod unchecked.
+                pub unsafe fn #method_ident(&self, #(#params),*) #output_type {
+                    #body
+                }
+            }
+        } else {
+            quote! {
+                #(#cfgs)*
+                pub fn #method_ident(&self, #(#params),*) #output_type {
+                    unsafe { #body }
+                }
+            }
+        });
+    }
+
+    let vis = &input.vis;
+    output.extend(quote! {
+        #[derive(Debug)]
+        #vis struct #wrapper(pub #this_ptr_type);
+
+        impl #wrapper {
+            #(#methods)*
+        }
+    });
+
+    output
+}
+
+fn is_c_string(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Ptr(ptr) if ptr.const_token.is_some() => match &*ptr.elem {
+            syn::Type::Path(path) => path.path.segments.last().is_some_and(|segment| segment.ident == "c_char"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// Returns the number of arguments a plugin must pass to a native, along with the indices of any required parameters that
+// follow an optional one. Optional parameters must all come after the required ones, as plugins can only omit trailing
+// arguments. The first parameter is the plugin context and never counts.
+fn native_args_minimum(param_optional: &[bool]) -> (i32, Vec<usize>) {
+    let mut args_minimum = 0;
+    let mut misplaced = Vec::new();
+    for (idx, &optional) in param_optional.iter().enumerate().skip(1) {
+        if !optional {
+            if args_minimum != idx - 1 {
+                misplaced.push(idx);
+            }
+            args_minimum = idx;
+        }
+    }
+
+    (args_minimum as i32, misplaced)
+}
+
+// Returns `T` for an `Option<T>` type.
+fn option_inner_type(ty: &syn::Type) -> Option<&syn::Type> {
+    let segment = match ty {
+        syn::Type::Path(ty) if ty.qself.is_none() => ty.path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != "Option" {
+        return None;
+    }
+
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match args.args.first() {
+            Some(syn::GenericArgument::Type(ty)) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_var_args(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(ty) => ty.qself.is_none() && ty.path.segments.last().is_some_and(|segment| segment.ident == "SPVarArgs"),
+        _ => false,
+    }
+}
+
+fn parse_default_attr(input: syn::parse::ParseStream) -> syn::Result<syn::Expr> {
+    input.parse::<syn::Token![=]>()?;
+    input.parse()
+}
+
+// Interface pointers are type aliases for raw pointers, and are named as such.
+fn is_pointer(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Ptr(_) => true,
+        syn::Type::Path(path) => path.path.segments.last().is_some_and(|segment| segment.ident.to_string().ends_with("Ptr")),
+        _ => false,
+    }
+}
+
+fn is_unit(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Tuple(tuple) => tuple.elems.is_empty(),
+        _ => false,
+    }
+}
+
+fn pascal_to_snake_case(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+            if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_is_lower) {
+                out.push('_');
+            }
+        }
+
+        out.extend(c.to_lowercase());
+    }
+
+    out
+}
+
+// Builds a C++ vtable for a Rust type from an inherent impl block, with one method per vtable slot.
+// The type must be #[repr(C)] with the vtable pointer as its first field. Slot names default to the
+// PascalCase method name, use #[vtable_slot(Name)] where that doesn't match the C++ name.
+#[proc_macro_attribute]
+pub fn vtable_adapter(attr: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let vtable_type = syn::parse_macro_input!(attr as syn::Path);
+    let mut input = syn::parse_macro_input!(item as syn::ItemImpl);
+    let mut output = TokenStream::new();
+
+    if let Some((_, path, _)) = &input.trait_ {
+        let span = path.span();
+        output.extend(error("Vtable adapter must be an inherent impl block", span, span));
+    }
+
+    let mut slots = Vec::new();
+    let mut thunks = Vec::new();
+    for item in &mut input.items {
+        let method = match item {
+            syn::ImplItem::Method(method) => method,
+            _ => {
+                let span = item.span();
+                output.extend(error("Vtable adapter impl blocks must only contain methods", span, span));
+                continue;
+            }
+        };
+
+        let mut slot = None;
+        let mut attr_error = None;
+        method.attrs.retain(|attr| {
+            if !attr.path.is_ident("vtable_slot") {
+                return true;
+            }
+
+            match attr.parse_args::<Ident>() {
+                Ok(ident) => slot = Some(ident),
+                Err(e) => attr_error = Some(e.to_compile_error()),
+            }
+
+            false
+        });
+        output.extend(attr_error);
+
+        let method_ident = &method.sig.ident;
+        let slot = slot.unwrap_or_else(|| format_ident!("{}", snake_to_pascal_case(&method_ident.to_string()), span = method_ident.span()));
+
+        let by_reference = matches!(method.sig.inputs.first(), Some(syn::FnArg::Receiver(receiver)) if receiver.reference.is_some());
+        if !by_reference {
+            let span = method.sig.span();
+            output.extend(error("Vtable adapter methods must take self by reference", span, span));
+            continue;
+        }
+
+        let mut thunk_inputs = Vec::new();
+        let mut thunk_args = Vec::new();
+        for (idx, input) in method.sig.inputs.iter().enumerate() {
+            if let syn::FnArg::Typed(input) = input {
+                let arg = format_ident!("arg{}", idx);
+                let ty = &input.ty;
+                thunk_inputs.push(quote_spanned!(input.span() => #arg: #ty));
+                thunk_args.push(arg);
+            }
+        }
+
+        // Slots that only exist on some targets, such as the second Itanium destructor.
+        let method_cfgs: Vec<_> = method.attrs.iter().filter(|attr| attr.path.is_ident("cfg")).collect();
+
+        let thunk_ident = format_ident!("__{}_thunk", method_ident);
+        let output_type = &method.sig.output;
+        for (cfg, abi) in member_function_abis() {
+            thunks.push(quote! {
+                #cfg
+                #(#method_cfgs)*
+                unsafe #abi fn #thunk_ident(this: *mut *mut #vtable_type, #(#thunk_inputs),*) #output_type {
+                    (*this.cast::<Self>()).#method_ident(#(#thunk_args),*)
+                }
+            });
+        }
+
+        slots.push(quote_spanned!(method.sig.span() => #(#method_cfgs)* #slot: Self::#thunk_ident));
+    }
+
+    let (impl_generics, _, where_clause) = input.generics.split_for_impl();
+    let self_ty = &input.self_ty;
+    output.extend(input.to_token_stream());
+    output.extend(quote! {
+        impl #impl_generics #self_ty #where_clause {
+            fn vtable() -> #vtable_type {
+                #vtable_type {
+                    #(#slots),*
+                }
+            }
+
+            #(#thunks)*
+        }
+    });
+
+    output.into()
+}
+
+fn snake_to_pascal_case(s: &str) -> String {
+    s.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+// C++ member functions use thiscall on 32-bit Windows. Everywhere else (Itanium on Linux, and all 64-bit targets)
+// they use the platform's C calling convention with `this` passed as the first argument.
+fn member_function_abis() -> Vec<(syn::Attribute, syn::Abi)> {
+    vec![
+        (syn::parse_quote!(#[cfg(all(windows, target_arch = "x86"))]), syn::parse_quote!(extern "thiscall")),
+        (syn::parse_quote!(#[cfg(not(all(windows, target_arch = "x86")))]), syn::parse_quote!(extern "C")),
+    ]
+}
+
 fn error(s: &str, start: Span, end: Span) -> TokenStream {
-    let mut v = Vec::new();
-    v.push(respan(Literal::string(&s), Span::call_site()));
+    let v = vec![respan(Literal::string(s), Span::call_site())];
     let group = v.into_iter().collect();
 
-    let mut r = Vec::<TokenTree>::new();
-    r.push(respan(Ident::new("compile_error", start), start));
-    r.push(respan(Punct::new('!', Spacing::Alone), Span::call_site()));
-    r.push(respan(Group::new(Delimiter::Brace, group), end));
+    let r: Vec<TokenTree> = vec![
+        respan(Ident::new("compile_error", start), start),
+        respan(Punct::new('!', Spacing::Alone), Span::call_site()),
+        respan(Group::new(Delimiter::Brace, group), end),
+    ];
 
     r.into_iter().collect()
 }
@@ -344,3 +975,41 @@ fn respan<T: Into<TokenTree>>(t: T, span: Span) -> TokenTree {
     t.set_span(span);
     t
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_args_minimum_counts_required_params() {
+        assert_eq!(native_args_minimum(&[false]), (0, vec![]));
+        assert_eq!(native_args_minimum(&[false, false, false]), (2, vec![]));
+        assert_eq!(native_args_minimum(&[false, false, true, true]), (1, vec![]));
+        assert_eq!(native_args_minimum(&[false, true, true]), (0, vec![]));
+        assert_eq!(native_args_minimum(&[true, false]), (1, vec![]));
+    }
+
+    #[test]
+    fn native_args_minimum_rejects_required_after_optional() {
+        assert_eq!(native_args_minimum(&[false, true, false]), (2, vec![2]));
+        assert_eq!(native_args_minimum(&[false, false, true, false, true, false]), (5, vec![3, 5]));
+    }
+
+    #[test]
+    fn pascal_to_snake_case_splits_words() {
+        assert_eq!(pascal_to_snake_case("GetInterfaceName"), "get_interface_name");
+        assert_eq!(pascal_to_snake_case("GetExtensionURL"), "get_extension_url");
+        assert_eq!(pascal_to_snake_case("IsMapRunning"), "is_map_running");
+        assert_eq!(pascal_to_snake_case("GetAPIVersion"), "get_api_version");
+        assert_eq!(pascal_to_snake_case("OnPluginCreated2"), "on_plugin_created2");
+        assert_eq!(pascal_to_snake_case("Execute"), "execute");
+    }
+
+    #[test]
+    fn snake_to_pascal_case_joins_words() {
+        assert_eq!(snake_to_pascal_case("on_extension_load"), "OnExtensionLoad");
+        assert_eq!(snake_to_pascal_case("get_api_version"), "GetApiVersion");
+        assert_eq!(snake_to_pascal_case("execute"), "Execute");
+        assert_eq!(snake_to_pascal_case("on_plugin_created2"), "OnPluginCreated2");
+    }
+}
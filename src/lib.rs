@@ -1,15 +1,19 @@
-#![feature(abi_thiscall)]
 #![allow(non_snake_case, non_camel_case_types, unused_variables)]
 
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+compile_error!("SourceMod is only available for x86 and x86_64 targets");
+
 pub use sm_ext_derive::*;
 
 pub mod types {
     use super::vtables::*;
-    use crate::IPluginContext;
+    use crate::{IPluginContext, PluginFunction};
+    use std::cell::RefCell;
     use std::convert::TryFrom;
     use std::ffi::{CStr, CString};
     use std::fmt::{Error, Formatter};
-    use std::os::raw::{c_char, c_uchar, c_uint};
+    use std::marker::PhantomData;
+    use std::os::raw::{c_char, c_int, c_uchar, c_uint, c_void};
 
     #[repr(transparent)]
     pub struct IdentityType(c_uint);
@@ -18,10 +22,114 @@ pub mod types {
     #[repr(transparent)]
     pub struct FeatureType(c_uchar);
 
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SPNullType(pub c_int);
+
+    impl SPNullType {
+        pub const VECTOR: SPNullType = SPNullType(0);
+        pub const STRING: SPNullType = SPNullType(1);
+    }
+
+    /// How the return values of the functions called by a forward are combined.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ExecType(pub c_int);
+
+    impl ExecType {
+        /// Ignore all return values, return 0.
+        pub const IGNORE: ExecType = ExecType(0);
+        /// Only return the last exec, ignore all others.
+        pub const SINGLE: ExecType = ExecType(1);
+        /// Acts as an event with the `Plugin_*` values, no mid-Stops allowed, returns the highest.
+        pub const EVENT: ExecType = ExecType(2);
+        /// Acts as a hook with the `Plugin_*` values, mid-Stops allowed, returns the highest.
+        pub const HOOK: ExecType = ExecType(3);
+    }
+
+    /// The type of a forward parameter.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ParamType(pub c_int);
+
+    impl ParamType {
+        pub const ANY: ParamType = ParamType(0);
+        pub const CELL: ParamType = ParamType(1 << 1);
+        pub const FLOAT: ParamType = ParamType(2 << 1);
+        pub const STRING: ParamType = ParamType((3 << 1) | 1);
+        pub const ARRAY: ParamType = ParamType((4 << 1) | 1);
+        pub const VAR_ARGS: ParamType = ParamType(5 << 1);
+        pub const CELL_BY_REF: ParamType = ParamType((1 << 1) | 1);
+        pub const FLOAT_BY_REF: ParamType = ParamType((2 << 1) | 1);
+    }
+
     // TODO: This should be a checked enum.
     #[repr(transparent)]
     pub struct FeatureStatus(c_uchar);
 
+    /// The result of a callback, such as a timer's, mirroring the `Plugin_*` values.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct ResultType(pub c_int);
+
+    impl ResultType {
+        pub const CONTINUE: ResultType = ResultType(0);
+        pub const CHANGED: ResultType = ResultType(1);
+        pub const HANDLED: ResultType = ResultType(3);
+        pub const STOP: ResultType = ResultType(4);
+    }
+
+    /// What a path passed to `ISourceMod::BuildPath` is relative to.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PathType(pub c_int);
+
+    impl PathType {
+        /// The path is used as-is.
+        pub const NONE: PathType = PathType(0);
+        /// The path is relative to the game (mod) folder.
+        pub const GAME: PathType = PathType(1);
+        /// The path is relative to the SourceMod folder.
+        pub const SM: PathType = PathType(2);
+        /// Like `SM`, but the result is relative to the game folder.
+        pub const SM_REL: PathType = PathType(3);
+    }
+
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ThreadFlags(pub c_uint);
+
+    impl ThreadFlags {
+        pub const DEFAULT: ThreadFlags = ThreadFlags(0);
+        /// The thread handle is destroyed as soon as the thread ends, it mustn't be used after that.
+        pub const AUTO_RELEASE: ThreadFlags = ThreadFlags(1 << 0);
+        pub const CREATE_SUSPENDED: ThreadFlags = ThreadFlags(1 << 1);
+    }
+
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TimerFlags(pub c_int);
+
+    impl TimerFlags {
+        pub const NONE: TimerFlags = TimerFlags(0);
+        /// The timer repeats until it returns [`ResultType::STOP`] or is killed.
+        pub const REPEAT: TimerFlags = TimerFlags(1 << 0);
+        /// The timer is killed when the map ends.
+        pub const NO_MAPCHANGE: TimerFlags = TimerFlags(1 << 1);
+
+        pub fn contains(self, other: TimerFlags) -> bool {
+            self.0 & other.0 == other.0
+        }
+    }
+
+    impl std::ops::BitOr for TimerFlags {
+        type Output = TimerFlags;
+
+        fn bitor(self, rhs: TimerFlags) -> TimerFlags {
+            TimerFlags(self.0 | rhs.0)
+        }
+    }
+
     #[repr(transparent)]
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
     pub struct cell_t(i32);
@@ -32,6 +140,169 @@ pub mod types {
         }
     }
 
+    /// A SourcePawn VM error code, as returned by most `IPluginContext` functions.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SPError(pub c_int);
+
+    impl SPError {
+        pub const NONE: SPError = SPError(0);
+        pub const HEAPLOW: SPError = SPError(3);
+        pub const PARAM: SPError = SPError(4);
+        pub const INVALID_ADDRESS: SPError = SPError(5);
+        pub const NOT_FOUND: SPError = SPError(6);
+        pub const INDEX: SPError = SPError(7);
+        pub const STACKLOW: SPError = SPError(8);
+        pub const MEMACCESS: SPError = SPError(11);
+        pub const HEAPMIN: SPError = SPError(13);
+        pub const ARRAY_BOUNDS: SPError = SPError(15);
+        pub const NATIVE: SPError = SPError(23);
+        pub const NOT_RUNNABLE: SPError = SPError(24);
+        pub const ABORTED: SPError = SPError(25);
+
+        pub fn message(&self) -> &'static str {
+            match self.0 {
+                0 => "No error",
+                1 => "Unrecognizable file format",
+                2 => "Decompressor was not found",
+                3 => "Not enough space on the heap",
+                4 => "Invalid parameter or parameter type",
+                5 => "Invalid plugin address",
+                6 => "Object or index not found",
+                7 => "Invalid index or index not found",
+                8 => "Not enough space on the stack",
+                9 => "Debug section not found or debug not enabled",
+                10 => "Invalid instruction",
+                11 => "Invalid memory access",
+                12 => "Stack went below stack boundary",
+                13 => "Heap went below heap boundary",
+                14 => "Divide by zero",
+                15 => "Array index is out of bounds",
+                16 => "Instruction contained invalid parameter",
+                17 => "Stack memory leaked by native",
+                18 => "Heap memory leaked by native",
+                19 => "Dynamic array is too big",
+                20 => "Tracker stack is out of bounds",
+                21 => "Native is not bound",
+                22 => "Maximum number of parameters reached",
+                23 => "Native detected error",
+                24 => "Plugin not runnable",
+                25 => "Call was aborted",
+                26 => "Plugin format is too old",
+                27 => "Plugin format is too new",
+                28 => "Out of memory",
+                29 => "Integer overflow",
+                30 => "Script execution timed out",
+                31 => "Custom error",
+                32 => "Fatal error",
+                _ => "Unknown error",
+            }
+        }
+    }
+
+    impl std::fmt::Display for SPError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            write!(f, "{} ({})", self.message(), self.0)
+        }
+    }
+
+    impl std::error::Error for SPError {}
+
+    pub type HandleType_t = c_uint;
+    pub type Handle_t = c_uint;
+
+    /// An error code returned by the `IHandleSys` functions.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct HandleError(pub c_int);
+
+    impl HandleError {
+        pub const NONE: HandleError = HandleError(0);
+        pub const CHANGED: HandleError = HandleError(1);
+        pub const TYPE: HandleError = HandleError(2);
+        pub const FREED: HandleError = HandleError(3);
+        pub const INDEX: HandleError = HandleError(4);
+        pub const ACCESS: HandleError = HandleError(5);
+        pub const LIMIT: HandleError = HandleError(6);
+        pub const IDENTITY: HandleError = HandleError(7);
+        pub const OWNER: HandleError = HandleError(8);
+        pub const VERSION: HandleError = HandleError(9);
+        pub const PARAMETER: HandleError = HandleError(10);
+        pub const NO_INHERIT: HandleError = HandleError(11);
+
+        pub fn message(&self) -> &'static str {
+            match self.0 {
+                0 => "No error",
+                1 => "The handle has been freed and reassigned",
+                2 => "The handle has a different type registered",
+                3 => "The handle has been freed",
+                4 => "Generic internal indexing error",
+                5 => "No access permitted to free this handle",
+                6 => "The limited number of handles has been reached",
+                7 => "The identity token was not usable",
+                8 => "Owners do not match for this operation",
+                9 => "Unrecognized security structure version",
+                10 => "An invalid parameter was passed",
+                11 => "This type cannot be inherited",
+                _ => "Unknown error",
+            }
+        }
+    }
+
+    impl std::fmt::Display for HandleError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            write!(f, "{} ({})", self.message(), self.0)
+        }
+    }
+
+    impl std::error::Error for HandleError {}
+
+    /// Permissions for a handle type, passed to `IHandleSys::CreateType`.
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy)]
+    pub struct TypeAccess {
+        pub hsVersion: c_uint,
+        pub ident: IdentityTokenPtr,
+        pub access: [bool; 2],
+    }
+
+    impl TypeAccess {
+        /// Index into `access`, whether identities other than the type's can create handles of it.
+        pub const CREATE: usize = 0;
+        /// Index into `access`, whether identities other than the type's can inherit from it.
+        pub const INHERIT: usize = 1;
+    }
+
+    /// Default permissions for the handles of a type, passed to `IHandleSys::CreateType`.
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy)]
+    pub struct HandleAccess {
+        pub hsVersion: c_uint,
+        pub access: [c_uint; 3],
+    }
+
+    impl HandleAccess {
+        /// Index into `access`, the restrictions on reading the handle's object.
+        pub const READ: usize = 0;
+        /// Index into `access`, the restrictions on freeing the handle.
+        pub const DELETE: usize = 1;
+        /// Index into `access`, the restrictions on cloning the handle.
+        pub const CLONE: usize = 2;
+
+        /// Only the identity that owns the handle type is allowed.
+        pub const RESTRICT_IDENTITY: c_uint = 1 << 0;
+        /// Only the owner of the handle is allowed.
+        pub const RESTRICT_OWNER: c_uint = 1 << 1;
+    }
+
+    /// The owner and identity used to check access to a handle.
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy)]
+    pub struct HandleSecurity {
+        pub pOwner: IdentityTokenPtr,
+        pub pIdentity: IdentityTokenPtr,
+    }
+
     pub trait TryFromWithContext<'a, T>: Sized {
         type Error;
 
@@ -90,6 +361,86 @@ pub mod types {
         }
     }
 
+    impl From<bool> for cell_t {
+        fn from(x: bool) -> Self {
+            cell_t(x as i32)
+        }
+    }
+
+    impl From<cell_t> for bool {
+        fn from(x: cell_t) -> Self {
+            x.0 != 0
+        }
+    }
+
+    impl From<char> for cell_t {
+        fn from(x: char) -> Self {
+            cell_t(x as i32)
+        }
+    }
+
+    impl TryFrom<cell_t> for char {
+        type Error = &'static str;
+
+        fn try_from(x: cell_t) -> Result<Self, Self::Error> {
+            u32::try_from(x.0).ok().and_then(char::from_u32).ok_or("Invalid character")
+        }
+    }
+
+    impl From<()> for cell_t {
+        fn from(_: ()) -> Self {
+            cell_t(0)
+        }
+    }
+
+    // Integers that always fit in a cell.
+    macro_rules! impl_small_int_conversions {
+        ($($ty:ty),* $(,)?) => {
+            $(
+                impl From<$ty> for cell_t {
+                    fn from(x: $ty) -> Self {
+                        cell_t(i32::from(x))
+                    }
+                }
+
+                impl TryFrom<cell_t> for $ty {
+                    type Error = &'static str;
+
+                    fn try_from(x: cell_t) -> Result<Self, Self::Error> {
+                        <$ty>::try_from(x.0).map_err(|_| "Integer out of range")
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_small_int_conversions!(i8, u8, i16, u16);
+
+    // Integers that might not fit in a cell, or a cell might not fit in.
+    macro_rules! impl_wide_int_conversions {
+        ($($ty:ty),* $(,)?) => {
+            $(
+                impl TryFrom<$ty> for cell_t {
+                    type Error = &'static str;
+
+                    fn try_from(x: $ty) -> Result<Self, Self::Error> {
+                        i32::try_from(x).map(cell_t).map_err(|_| "Integer out of range")
+                    }
+                }
+
+                impl TryFrom<cell_t> for $ty {
+                    type Error = &'static str;
+
+                    fn try_from(x: cell_t) -> Result<Self, Self::Error> {
+                        <$ty>::try_from(x.0).map_err(|_| "Integer out of range")
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_wide_int_conversions!(u32, i64, u64, isize, usize);
+
     impl TryFromWithContext<'_, cell_t> for CString {
         type Error = &'static str;
 
@@ -106,149 +457,1052 @@ pub mod types {
 
         fn try_from_plugin(ctx: &'a IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
             match ctx.local_to_string(value) {
-                Ok(s) => Ok(s),
+                Ok(s) => {
+                    borrow_cells(value, s.to_bytes_with_nul().len(), CellAccess::Shared)?;
+                    Ok(s)
+                }
                 Err(_) => Err("Invalid memory address"),
             }
         }
     }
 
-    // TODO: These &mut implementations seem risky, maybe a SPRef/SPString/SPArray wrapper object would be a better way to go...
+    /// A native argument along with its size, taken from another argument or given as a fixed value.
+    ///
+    /// This is what `#[size(...)]` parameters of a `#[native]` are converted from.
+    #[derive(Debug, Clone, Copy)]
+    pub struct SizedArg {
+        pub value: cell_t,
+        pub size: cell_t,
+    }
+
+    /// A plugin `char[]` buffer and its maximum length, for natives that output a string.
+    ///
+    /// Use `#[size(maxlength)]` on the parameter to pair it with the argument holding the buffer size.
+    #[derive(Debug)]
+    pub struct SPStringBuffer<'a> {
+        ctx: &'a IPluginContext,
+        local_addr: cell_t,
+        maxlength: usize,
+    }
+
+    impl SPStringBuffer<'_> {
+        pub fn maxlength(&self) -> usize {
+            self.maxlength
+        }
+
+        /// Writes a string to the buffer, truncating it if needed. Returns the number of bytes written.
+        pub fn write_cstr(&mut self, value: &CStr) -> Result<usize, SPError> {
+            check_cells_writable(self.local_addr, self.maxlength)?;
+            self.ctx.string_to_local_utf8(self.local_addr, self.maxlength, value)
+        }
+
+        /// Writes a string to the buffer, truncating it if needed. Returns the number of bytes written.
+        ///
+        /// Anything after an embedded NUL character is ignored, as it would be by the plugin.
+        pub fn write(&mut self, value: &str) -> Result<usize, SPError> {
+            write_local_str(self.ctx, self.local_addr, self.maxlength, value)
+        }
+    }
+
+    fn write_local_str(ctx: &IPluginContext, local_addr: cell_t, maxlength: usize, value: &str) -> Result<usize, SPError> {
+        check_cells_writable(local_addr, maxlength)?;
+
+        let len = value.find('\0').unwrap_or(value.len());
+        let mut bytes = Vec::with_capacity(len + 1);
+        bytes.extend_from_slice(&value.as_bytes()[..len]);
+        bytes.push(0);
+
+        ctx.string_to_local_utf8(local_addr, maxlength, CStr::from_bytes_with_nul(&bytes).unwrap())
+    }
 
-    impl<'a> TryFromWithContext<'a, cell_t> for &'a mut cell_t {
+    impl<'a> TryFromWithContext<'a, SizedArg> for SPStringBuffer<'a> {
         type Error = &'static str;
 
-        fn try_from_plugin(ctx: &'a IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
-            match ctx.local_to_phys_addr(value) {
-                Ok(s) => Ok(s),
-                Err(_) => Err("Invalid memory address"),
+        fn try_from_plugin(ctx: &'a IPluginContext, SizedArg { value: local_addr, size: maxlength }: SizedArg) -> Result<Self, Self::Error> {
+            let maxlength = usize::try_from(i32::from(maxlength)).map_err(|_| "Invalid buffer size")?;
+
+            // Make sure the whole buffer is in plugin memory, as SourcePawn only checks the start address.
+            if ctx.local_to_phys_addr(local_addr).is_err() {
+                return Err("Invalid memory address");
             }
+
+            if maxlength > 0 && ctx.local_to_phys_addr(cell_t(local_addr.0.wrapping_add(maxlength as i32 - 1))).is_err() {
+                return Err("Buffer size exceeds plugin memory");
+            }
+
+            borrow_cells(local_addr, maxlength, CellAccess::Copied)?;
+
+            Ok(SPStringBuffer { ctx, local_addr, maxlength })
         }
     }
 
-    impl<'a> TryFromWithContext<'a, cell_t> for &'a mut i32 {
-        type Error = &'static str;
+    /// A SourcePawn `bool` stored in a cell, for reading and writing plugin `bool[]` arrays in place.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SPBool(cell_t);
 
-        fn try_from_plugin(ctx: &'a IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
-            let cell: &mut cell_t = value.try_into_plugin(ctx)?;
-            unsafe { Ok(&mut *(cell as *mut cell_t as *mut i32)) }
+    impl From<bool> for SPBool {
+        fn from(x: bool) -> Self {
+            SPBool(cell_t(x as i32))
         }
     }
 
-    impl<'a> TryFromWithContext<'a, cell_t> for &'a mut f32 {
-        type Error = &'static str;
+    impl From<SPBool> for bool {
+        fn from(x: SPBool) -> Self {
+            x.0 .0 != 0
+        }
+    }
 
-        fn try_from_plugin(ctx: &'a IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
-            let cell: &mut cell_t = value.try_into_plugin(ctx)?;
-            unsafe { Ok(&mut *(cell as *mut cell_t as *mut f32)) }
+    // Checks that an array of `size` cells starting at `local_addr` is entirely within plugin memory.
+    fn local_to_phys_array(ctx: &IPluginContext, local_addr: cell_t, size: cell_t) -> Result<(*mut cell_t, usize), &'static str> {
+        let size = usize::try_from(i32::from(size)).map_err(|_| "Invalid array size")?;
+        let addr = ctx.local_to_phys_addr(local_addr).map_err(|_| "Invalid memory address")? as *mut cell_t;
+
+        if size > 0 {
+            let last = i32::try_from((size - 1) * std::mem::size_of::<cell_t>()).ok().and_then(|offset| local_addr.0.checked_add(offset));
+            match last {
+                Some(last) if ctx.local_to_phys_addr(cell_t(last)).is_ok() => {}
+                _ => return Err("Array size exceeds plugin memory"),
+            }
         }
+
+        Ok((addr, size))
     }
 
-    #[repr(C)]
-    pub struct NativeInfo {
-        pub name: *const c_char,
-        pub func: Option<unsafe extern "C" fn(ctx: IPluginContextPtr, args: *const cell_t) -> cell_t>,
+    macro_rules! impl_array_conversions {
+        ($($ty:ty),* $(,)?) => {
+            $(
+                impl<'a> TryFromWithContext<'a, SizedArg> for &'a [$ty] {
+                    type Error = &'static str;
+
+                    fn try_from_plugin(ctx: &'a IPluginContext, SizedArg { value: local_addr, size }: SizedArg) -> Result<Self, Self::Error> {
+                        let (addr, size) = local_to_phys_array(ctx, local_addr, size)?;
+                        borrow_cells(local_addr, size * std::mem::size_of::<$ty>(), CellAccess::Shared)?;
+                        unsafe { Ok(std::slice::from_raw_parts(addr as *const $ty, size)) }
+                    }
+                }
+
+                impl<'a> TryFromWithContext<'a, SizedArg> for &'a mut [$ty] {
+                    type Error = &'static str;
+
+                    fn try_from_plugin(ctx: &'a IPluginContext, SizedArg { value: local_addr, size }: SizedArg) -> Result<Self, Self::Error> {
+                        let (addr, size) = local_to_phys_array(ctx, local_addr, size)?;
+                        borrow_cells(local_addr, size * std::mem::size_of::<$ty>(), CellAccess::Exclusive)?;
+                        unsafe { Ok(std::slice::from_raw_parts_mut(addr as *mut $ty, size)) }
+                    }
+                }
+            )*
+        };
     }
 
-    pub struct IdentityToken();
-    pub type IdentityTokenPtr = *mut IdentityToken;
+    // All of these share their representation with cell_t.
+    impl_array_conversions!(cell_t, i32, f32, SPBool);
 
-    pub type IExtensionInterfacePtr = *mut *mut IExtensionInterfaceVtable;
-    pub type IExtensionPtr = *mut *mut IExtensionVtable;
-    pub type SMInterfacePtr = *mut *mut SMInterfaceVtable;
-    pub type IShareSysPtr = *mut *mut IShareSysVtable;
-    pub type IFeatureProviderPtr = *mut *mut IFeatureProviderVtable;
-    pub type IPluginRuntimePtr = *mut *mut IPluginRuntimeVtable;
-    pub type IPluginContextPtr = *mut *mut IPluginContextVtable;
-}
+    // Plugin memory used by the arguments of each native call running on this thread, innermost call last.
+    // Arguments of the same call must not alias a mutable reference, or be written while a shared reference to them is alive.
+    thread_local! {
+        static BORROWED_CELLS: RefCell<Vec<Vec<(i64, i64, CellAccess)>>> = const { RefCell::new(Vec::new()) };
+    }
 
-pub(self) mod vtables {
-    use super::types::*;
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum CellAccess {
+        // A shared Rust reference, such as `&[i32]` or `&CStr`.
+        Shared,
+        // A mutable Rust reference, such as `&mut [i32]`.
+        Exclusive,
+        // Read and written by copying values, such as `SPRef` or `SPStringBuffer`, checked with `check_cells_writable` before writing.
+        Copied,
+    }
 
-    use libc::size_t;
-    use sm_ext_derive::vtable;
-    use std::os::raw::{c_char, c_int, c_uint, c_void};
+    pub(crate) fn begin_native_call() {
+        BORROWED_CELLS.with(|calls| calls.borrow_mut().push(Vec::new()));
+    }
 
-    #[vtable(IExtensionInterfacePtr)]
-    pub struct IExtensionInterfaceVtable {
-        pub GetExtensionVersion: fn() -> i32,
-        pub OnExtensionLoad: fn(me: IExtensionPtr, sys: IShareSysPtr, error: *mut c_char, maxlength: size_t, late: bool) -> bool,
-        pub OnExtensionUnload: fn() -> (),
-        pub OnExtensionsAllLoaded: fn() -> (),
-        pub OnExtensionPauseChange: fn(pause: bool) -> (),
-        pub QueryInterfaceDrop: fn(interface: SMInterfacePtr) -> bool,
-        pub NotifyInterfaceDrop: fn(interface: SMInterfacePtr) -> (),
-        pub QueryRunning: fn(error: *mut c_char, maxlength: size_t) -> bool,
-        pub IsMetamodExtension: fn() -> bool,
-        pub GetExtensionName: fn() -> *const c_char,
-        pub GetExtensionURL: fn() -> *const c_char,
-        pub GetExtensionTag: fn() -> *const c_char,
-        pub GetExtensionAuthor: fn() -> *const c_char,
-        pub GetExtensionVerString: fn() -> *const c_char,
-        pub GetExtensionDescription: fn() -> *const c_char,
-        pub GetExtensionDateString: fn() -> *const c_char,
-        pub OnCoreMapStart: fn(edict_list: *mut c_void, edict_count: c_int, client_max: c_int) -> (),
-        pub OnDependenciesDropped: fn() -> (),
-        pub OnCoreMapEnd: fn() -> (),
+    pub(crate) fn end_native_call() {
+        BORROWED_CELLS.with(|calls| calls.borrow_mut().pop());
     }
 
-    #[vtable(IExtensionPtr)]
-    pub struct IExtensionVtable {
-        pub IsLoaded: fn() -> bool,
-        pub GetAPI: fn() -> IExtensionInterfacePtr,
-        pub GetFilename: fn() -> *const c_char,
-        pub GetIdentity: fn() -> IdentityTokenPtr,
-        _FindFirstDependency: fn() -> *mut c_void,
-        _FindNextDependency: fn() -> *mut c_void,
-        _FreeDependencyIterator: fn() -> *mut c_void,
-        pub IsRunning: fn(error: *mut c_char, maxlength: size_t) -> bool,
-        pub IsExternal: fn() -> bool,
+    // Records `bytes` bytes of plugin memory at `local_addr` as used by an argument of the current native call.
+    fn borrow_cells(local_addr: cell_t, bytes: usize, access: CellAccess) -> Result<(), &'static str> {
+        if bytes == 0 {
+            return Ok(());
+        }
+
+        let start = i64::from(local_addr.0);
+        let end = start + bytes as i64;
+
+        BORROWED_CELLS.with(|calls| {
+            // Conversions outside of a native call aren't tracked.
+            let mut calls = calls.borrow_mut();
+            let borrowed = match calls.last_mut() {
+                Some(borrowed) => borrowed,
+                None => return Ok(()),
+            };
+
+            let exclusive = access == CellAccess::Exclusive;
+            if borrowed.iter().any(|&(other_start, other_end, other_access)| start < other_end && other_start < end && (exclusive || other_access == CellAccess::Exclusive)) {
+                return Err("Argument overlaps another argument's memory");
+            }
+
+            borrowed.push((start, end, access));
+            Ok(())
+        })
     }
 
-    #[vtable(SMInterfacePtr)]
-    pub struct SMInterfaceVtable {
-        pub GetInterfaceVersion: fn() -> c_uint,
-        pub GetInterfaceName: fn() -> *const c_char,
-        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+    // Checks that plugin memory can be written without changing what a shared reference from the current native call points to.
+    fn check_cells_writable(local_addr: cell_t, bytes: usize) -> Result<(), SPError> {
+        let start = i64::from(local_addr.0);
+        let end = start + bytes as i64;
+
+        BORROWED_CELLS.with(|calls| match calls.borrow().last() {
+            Some(borrowed) if borrowed.iter().any(|&(other_start, other_end, other_access)| start < other_end && other_start < end && other_access == CellAccess::Shared) => Err(SPError::MEMACCESS),
+            _ => Ok(()),
+        })
     }
 
-    #[vtable(IShareSysPtr)]
-    pub struct IShareSysVtable {
-        pub AddInterface: fn(myself: IExtensionPtr, iface: SMInterfacePtr) -> bool,
-        pub RequestInterface: fn(iface_name: *const c_char, iface_vers: c_uint, myself: IExtensionPtr, iface: *mut SMInterfacePtr) -> bool,
-        pub AddNatives: fn(myself: IExtensionPtr, natives: *const NativeInfo) -> (),
-        pub CreateIdentType: fn(name: *const c_char) -> IdentityType,
-        pub FindIdentType: fn(name: *const c_char) -> IdentityType,
-        pub CreateIdentity: fn(ident_type: IdentityType, ptr: *mut c_void) -> IdentityTokenPtr,
-        pub DestroyIdentType: fn(ident_type: IdentityType) -> (),
-        pub DestroyIdentity: fn(identity: IdentityTokenPtr) -> (),
-        pub AddDependency: fn(myself: IExtensionPtr, filename: *const c_char, require: bool, autoload: bool) -> (),
-        pub RegisterLibrary: fn(myself: IExtensionPtr, name: *const c_char) -> (),
-        _OverrideNatives: fn(myself: IExtensionPtr, natives: *const NativeInfo) -> (),
-        pub AddCapabilityProvider: fn(myself: IExtensionPtr, provider: IFeatureProviderPtr, name: *const c_char) -> (),
-        pub DropCapabilityProvider: fn(myself: IExtensionPtr, provider: IFeatureProviderPtr, name: *const c_char) -> (),
-        pub TestFeature: fn(rt: IPluginRuntimePtr, feature_type: FeatureType, name: *const c_char) -> FeatureStatus,
+    /// A value that is stored in a single cell, for use with [`SPRef`] and [`SPArray`].
+    pub trait CellValue: Copy {
+        fn from_cell(cell: cell_t) -> Self;
+        fn into_cell(self) -> cell_t;
     }
 
-    #[vtable(IFeatureProviderPtr)]
-    pub struct IFeatureProviderVtable {}
+    impl CellValue for cell_t {
+        fn from_cell(cell: cell_t) -> Self {
+            cell
+        }
 
-    #[vtable(IPluginRuntimePtr)]
-    pub struct IPluginRuntimeVtable {}
+        fn into_cell(self) -> cell_t {
+            self
+        }
+    }
 
-    #[vtable(IPluginContextPtr)]
-    pub struct IPluginContextVtable {
-        _Destructor: fn() -> (),
-        #[cfg(unix)]
-        _Destructor2: fn() -> (),
-        _GetVirtualMachine: fn(),
-        _GetContext: fn(),
-        _IsDebugging: fn(),
-        _SetDebugBreak: fn(),
-        _GetDebugInfo: fn(),
-        _HeapAlloc: fn(),
-        _HeapPop: fn(),
-        _HeapRelease: fn(),
-        _FindNativeByName: fn(),
+    impl CellValue for i32 {
+        fn from_cell(cell: cell_t) -> Self {
+            cell.into()
+        }
+
+        fn into_cell(self) -> cell_t {
+            self.into()
+        }
+    }
+
+    impl CellValue for f32 {
+        fn from_cell(cell: cell_t) -> Self {
+            cell.into()
+        }
+
+        fn into_cell(self) -> cell_t {
+            self.into()
+        }
+    }
+
+    impl CellValue for bool {
+        fn from_cell(cell: cell_t) -> Self {
+            cell.into()
+        }
+
+        fn into_cell(self) -> cell_t {
+            self.into()
+        }
+    }
+
+    impl CellValue for SPBool {
+        fn from_cell(cell: cell_t) -> Self {
+            SPBool(cell)
+        }
+
+        fn into_cell(self) -> cell_t {
+            self.0
+        }
+    }
+
+    /// A plugin variable passed by reference, such as `int &value`.
+    ///
+    /// The address is checked again on every access, and the value is copied in or out rather than borrowed.
+    #[derive(Debug)]
+    pub struct SPRef<'a, T> {
+        ctx: &'a IPluginContext,
+        local_addr: cell_t,
+        _marker: PhantomData<T>,
+    }
+
+    impl<T: CellValue> SPRef<'_, T> {
+        pub fn local_addr(&self) -> cell_t {
+            self.local_addr
+        }
+
+        pub fn get(&self) -> Result<T, SPError> {
+            Ok(T::from_cell(*self.ctx.local_to_phys_addr(self.local_addr)?))
+        }
+
+        pub fn set(&mut self, value: T) -> Result<(), SPError> {
+            check_cells_writable(self.local_addr, std::mem::size_of::<cell_t>())?;
+            *self.ctx.local_to_phys_addr(self.local_addr)? = value.into_cell();
+
+            Ok(())
+        }
+    }
+
+    impl<'a, T: CellValue> TryFromWithContext<'a, cell_t> for SPRef<'a, T> {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &'a IPluginContext, local_addr: cell_t) -> Result<Self, Self::Error> {
+            ctx.local_to_phys_addr(local_addr).map_err(|_| "Invalid memory address")?;
+            borrow_cells(local_addr, std::mem::size_of::<cell_t>(), CellAccess::Copied)?;
+
+            Ok(SPRef { ctx, local_addr, _marker: PhantomData })
+        }
+    }
+
+    /// A plugin array with a known size, such as `int[] values, int size`.
+    ///
+    /// Elements are checked again on every access, and copied in or out rather than borrowed.
+    #[derive(Debug)]
+    pub struct SPArray<'a, T> {
+        ctx: &'a IPluginContext,
+        local_addr: cell_t,
+        len: usize,
+        _marker: PhantomData<T>,
+    }
+
+    impl<T: CellValue> SPArray<'_, T> {
+        pub fn local_addr(&self) -> cell_t {
+            self.local_addr
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        fn element_addr(&self, index: usize) -> Result<cell_t, SPError> {
+            if index >= self.len {
+                return Err(SPError::ARRAY_BOUNDS);
+            }
+
+            Ok(cell_t(self.local_addr.0.wrapping_add((index * std::mem::size_of::<cell_t>()) as i32)))
+        }
+
+        pub fn get(&self, index: usize) -> Result<T, SPError> {
+            Ok(T::from_cell(*self.ctx.local_to_phys_addr(self.element_addr(index)?)?))
+        }
+
+        pub fn set(&mut self, index: usize, value: T) -> Result<(), SPError> {
+            let local_addr = self.element_addr(index)?;
+            check_cells_writable(local_addr, std::mem::size_of::<cell_t>())?;
+            *self.ctx.local_to_phys_addr(local_addr)? = value.into_cell();
+
+            Ok(())
+        }
+
+        pub fn to_vec(&self) -> Result<Vec<T>, SPError> {
+            (0..self.len).map(|i| self.get(i)).collect()
+        }
+    }
+
+    impl<'a, T: CellValue> TryFromWithContext<'a, SizedArg> for SPArray<'a, T> {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &'a IPluginContext, SizedArg { value: local_addr, size }: SizedArg) -> Result<Self, Self::Error> {
+            let (_, len) = local_to_phys_array(ctx, local_addr, size)?;
+            borrow_cells(local_addr, len * std::mem::size_of::<cell_t>(), CellAccess::Copied)?;
+
+            Ok(SPArray { ctx, local_addr, len, _marker: PhantomData })
+        }
+    }
+
+    /// A plugin string, such as `const char[] name`, or `char[] name, int maxlength` with `#[size(maxlength)]`.
+    ///
+    /// The string is checked again on every access, and copied in or out rather than borrowed.
+    /// It can only be written when its size is known.
+    #[derive(Debug)]
+    pub struct SPString<'a> {
+        ctx: &'a IPluginContext,
+        local_addr: cell_t,
+        maxlength: Option<usize>,
+    }
+
+    impl SPString<'_> {
+        pub fn local_addr(&self) -> cell_t {
+            self.local_addr
+        }
+
+        pub fn maxlength(&self) -> Option<usize> {
+            self.maxlength
+        }
+
+        pub fn get(&self) -> Result<CString, SPError> {
+            Ok(self.ctx.local_to_string(self.local_addr)?.to_owned())
+        }
+
+        /// Writes a string, truncating it if needed. Returns the number of bytes written.
+        pub fn set(&mut self, value: &str) -> Result<usize, SPError> {
+            match self.maxlength {
+                Some(maxlength) => write_local_str(self.ctx, self.local_addr, maxlength, value),
+                None => Err(SPError::PARAM),
+            }
+        }
+    }
+
+    impl<'a> TryFromWithContext<'a, cell_t> for SPString<'a> {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &'a IPluginContext, local_addr: cell_t) -> Result<Self, Self::Error> {
+            let s = ctx.local_to_string(local_addr).map_err(|_| "Invalid memory address")?;
+            borrow_cells(local_addr, s.to_bytes_with_nul().len(), CellAccess::Copied)?;
+
+            Ok(SPString { ctx, local_addr, maxlength: None })
+        }
+    }
+
+    impl<'a> TryFromWithContext<'a, SizedArg> for SPString<'a> {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &'a IPluginContext, value: SizedArg) -> Result<Self, Self::Error> {
+            let buffer = SPStringBuffer::try_from_plugin(ctx, value)?;
+
+            Ok(SPString { ctx, local_addr: buffer.local_addr, maxlength: Some(buffer.maxlength) })
+        }
+    }
+
+    /// A value stored in a fixed number of consecutive cells, making up a field of a SourcePawn `enum struct`.
+    ///
+    /// Use `#[derive(EnumStruct)]` to implement this for a struct with the same fields as the plugin's `enum struct`.
+    pub trait EnumStructField: Sized {
+        const CELLS: usize;
+
+        /// Reads the value from exactly `CELLS` cells.
+        fn read_cells(cells: &[cell_t]) -> Self;
+
+        /// Writes the value to exactly `CELLS` cells.
+        fn write_cells(&self, cells: &mut [cell_t]);
+    }
+
+    macro_rules! impl_enum_struct_field {
+        ($($ty:ty),* $(,)?) => {
+            $(
+                impl EnumStructField for $ty {
+                    const CELLS: usize = 1;
+
+                    fn read_cells(cells: &[cell_t]) -> Self {
+                        <$ty as CellValue>::from_cell(cells[0])
+                    }
+
+                    fn write_cells(&self, cells: &mut [cell_t]) {
+                        cells[0] = self.into_cell();
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_enum_struct_field!(cell_t, i32, f32, bool, SPBool);
+
+    impl<T: EnumStructField, const N: usize> EnumStructField for [T; N] {
+        const CELLS: usize = T::CELLS * N;
+
+        fn read_cells(cells: &[cell_t]) -> Self {
+            std::array::from_fn(|i| T::read_cells(&cells[i * T::CELLS..(i + 1) * T::CELLS]))
+        }
+
+        fn write_cells(&self, cells: &mut [cell_t]) {
+            for (i, value) in self.iter().enumerate() {
+                value.write_cells(&mut cells[i * T::CELLS..(i + 1) * T::CELLS]);
+            }
+        }
+    }
+
+    // A `char[N]` string, with the characters packed into cells as they are in plugin memory.
+    impl<const N: usize> EnumStructField for [u8; N] {
+        const CELLS: usize = N.div_ceil(std::mem::size_of::<cell_t>());
+
+        fn read_cells(cells: &[cell_t]) -> Self {
+            assert_eq!(cells.len(), Self::CELLS);
+            let bytes = unsafe { std::slice::from_raw_parts(cells.as_ptr() as *const u8, N) };
+
+            let mut value = [0; N];
+            value.copy_from_slice(bytes);
+            value
+        }
+
+        fn write_cells(&self, cells: &mut [cell_t]) {
+            assert_eq!(cells.len(), Self::CELLS);
+            let bytes = unsafe { std::slice::from_raw_parts_mut(cells.as_mut_ptr() as *mut u8, N) };
+
+            bytes.copy_from_slice(self);
+        }
+    }
+
+    /// A plugin `enum struct` passed to a native, read and written as a whole.
+    ///
+    /// The cells are checked again on every access, and copied in or out rather than borrowed.
+    #[derive(Debug)]
+    pub struct SPEnumStruct<'a, T> {
+        ctx: &'a IPluginContext,
+        local_addr: cell_t,
+        _marker: PhantomData<T>,
+    }
+
+    impl<T: EnumStructField> SPEnumStruct<'_, T> {
+        pub fn local_addr(&self) -> cell_t {
+            self.local_addr
+        }
+
+        fn cells_addr(&self) -> Result<*mut cell_t, SPError> {
+            let addr = self.ctx.local_to_phys_addr(self.local_addr)? as *mut cell_t;
+            if T::CELLS > 1 {
+                self.ctx.local_to_phys_addr(cell_t(self.local_addr.0.wrapping_add(((T::CELLS - 1) * std::mem::size_of::<cell_t>()) as i32)))?;
+            }
+
+            Ok(addr)
+        }
+
+        pub fn get(&self) -> Result<T, SPError> {
+            let cells = unsafe { std::slice::from_raw_parts(self.cells_addr()?, T::CELLS) };
+
+            Ok(T::read_cells(cells))
+        }
+
+        pub fn set(&mut self, value: &T) -> Result<(), SPError> {
+            check_cells_writable(self.local_addr, T::CELLS * std::mem::size_of::<cell_t>())?;
+            let cells = unsafe { std::slice::from_raw_parts_mut(self.cells_addr()?, T::CELLS) };
+            value.write_cells(cells);
+
+            Ok(())
+        }
+    }
+
+    impl<'a, T: EnumStructField> TryFromWithContext<'a, cell_t> for SPEnumStruct<'a, T> {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &'a IPluginContext, local_addr: cell_t) -> Result<Self, Self::Error> {
+            let size = i32::try_from(T::CELLS).map_err(|_| "Invalid enum struct size")?;
+            local_to_phys_array(ctx, local_addr, size.into())?;
+            borrow_cells(local_addr, T::CELLS * std::mem::size_of::<cell_t>(), CellAccess::Copied)?;
+
+            Ok(SPEnumStruct { ctx, local_addr, _marker: PhantomData })
+        }
+    }
+
+    /// A SourcePawn `float[3]`, used for positions, angles and velocities.
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Vector {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+    }
+
+    impl Vector {
+        pub fn new(x: f32, y: f32, z: f32) -> Self {
+            Vector { x, y, z }
+        }
+    }
+
+    impl From<[f32; 3]> for Vector {
+        fn from([x, y, z]: [f32; 3]) -> Self {
+            Vector { x, y, z }
+        }
+    }
+
+    impl From<Vector> for [f32; 3] {
+        fn from(v: Vector) -> Self {
+            [v.x, v.y, v.z]
+        }
+    }
+
+    impl EnumStructField for Vector {
+        const CELLS: usize = 3;
+
+        fn read_cells(cells: &[cell_t]) -> Self {
+            <[f32; 3]>::read_cells(cells).into()
+        }
+
+        fn write_cells(&self, cells: &mut [cell_t]) {
+            <[f32; 3]>::from(*self).write_cells(cells)
+        }
+    }
+
+    impl TryFromWithContext<'_, cell_t> for Vector {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
+            let (addr, _) = local_to_phys_array(ctx, value, cell_t(3))?;
+            let cells = unsafe { std::slice::from_raw_parts(addr, 3) };
+
+            Ok(Vector::read_cells(cells))
+        }
+    }
+
+    impl TryFromWithContext<'_, cell_t> for [f32; 3] {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
+            Vector::try_from_plugin(ctx, value).map(Into::into)
+        }
+    }
+
+    /// A native parameter type that the plugin can pass a null value for, such as `NULL_STRING` or `NULL_VECTOR`.
+    ///
+    /// An `Option` of one of these types is `None` when the plugin passes the null value, not just when the argument is missing.
+    pub trait NullableArg {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError>;
+    }
+
+    impl NullableArg for &CStr {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError> {
+            Ok(ctx.local_to_string_null(value)?.is_none())
+        }
+    }
+
+    impl NullableArg for CString {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError> {
+            Ok(ctx.local_to_string_null(value)?.is_none())
+        }
+    }
+
+    impl NullableArg for SPString<'_> {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError> {
+            Ok(ctx.local_to_string_null(value)?.is_none())
+        }
+    }
+
+    impl NullableArg for Vector {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError> {
+            ctx.is_null_vector(value)
+        }
+    }
+
+    impl NullableArg for [f32; 3] {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError> {
+            ctx.is_null_vector(value)
+        }
+    }
+
+    impl NullableArg for SPEnumStruct<'_, Vector> {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError> {
+            ctx.is_null_vector(value)
+        }
+    }
+
+    // A plugin `Function`, which is only valid while the plugin is loaded.
+    impl TryFromWithContext<'_, cell_t> for IPluginFunction {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
+            ctx.get_function_by_id(value.0 as funcid_t).ok_or("Invalid function id")
+        }
+    }
+
+    impl NullableArg for IPluginFunction {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError> {
+            // INVALID_FUNCTION
+            Ok(value.0 == -1)
+        }
+    }
+
+    impl TryFromWithContext<'_, cell_t> for PluginFunction {
+        type Error = &'static str;
+
+        fn try_from_plugin(ctx: &IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
+            PluginFunction::new(IPluginFunction::try_from_plugin(ctx, value)?)
+        }
+    }
+
+    impl NullableArg for PluginFunction {
+        fn is_null(ctx: &IPluginContext, value: cell_t) -> Result<bool, SPError> {
+            IPluginFunction::is_null(ctx, value)
+        }
+    }
+
+    /// An `Option` parameter of a `#[native]` that the plugin passed.
+    ///
+    /// The generated code converts this with [`TryFromOptionalArg`], which treats the null value of a [`NullableArg`] as `None`.
+    /// Parameters with a `#[size]` are passed as a [`SizedArg`] instead, and have no null value.
+    pub struct OptionalArg<T, V = cell_t>(pub V, pub PhantomData<T>);
+
+    pub trait TryFromOptionalArg<'a, T> {
+        fn try_from_optional_arg(&self, ctx: &'a IPluginContext) -> Result<Option<T>, Box<dyn std::error::Error>>;
+    }
+
+    // Preferred by method resolution when it applies, as it doesn't need another auto-ref.
+    impl<'a, T> TryFromOptionalArg<'a, T> for OptionalArg<T>
+    where
+        T: NullableArg + TryFromWithContext<'a, cell_t>,
+        T::Error: Into<Box<dyn std::error::Error>>,
+    {
+        fn try_from_optional_arg(&self, ctx: &'a IPluginContext) -> Result<Option<T>, Box<dyn std::error::Error>> {
+            if T::is_null(ctx, self.0)? {
+                return Ok(None);
+            }
+
+            T::try_from_plugin(ctx, self.0).map(Some).map_err(Into::into)
+        }
+    }
+
+    impl<'a, T, V: Copy> TryFromOptionalArg<'a, T> for &OptionalArg<T, V>
+    where
+        T: TryFromWithContext<'a, V>,
+        T::Error: Into<Box<dyn std::error::Error>>,
+    {
+        fn try_from_optional_arg(&self, ctx: &'a IPluginContext) -> Result<Option<T>, Box<dyn std::error::Error>> {
+            T::try_from_plugin(ctx, self.0).map(Some).map_err(Into::into)
+        }
+    }
+
+    /// The trailing `any ...` arguments of a native, which are each passed by reference.
+    ///
+    /// Must be the last parameter of a `#[native]`, and the values are only converted when accessed.
+    #[derive(Debug, Clone, Copy)]
+    pub struct SPVarArgs<'a> {
+        ctx: &'a IPluginContext,
+        args: &'a [cell_t],
+    }
+
+    impl<'a> SPVarArgs<'a> {
+        pub fn new(ctx: &'a IPluginContext, args: &'a [cell_t]) -> Self {
+            SPVarArgs { ctx, args }
+        }
+
+        pub fn len(&self) -> usize {
+            self.args.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.args.is_empty()
+        }
+
+        pub fn get(&self, index: usize) -> Option<SPVarArg<'a>> {
+            self.args.get(index).map(|&local_addr| SPVarArg { ctx: self.ctx, local_addr })
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = SPVarArg<'a>> + '_ {
+            self.args.iter().map(move |&local_addr| SPVarArg { ctx: self.ctx, local_addr })
+        }
+    }
+
+    /// Types that are converted from the address of a by-reference argument, which variadic arguments always are.
+    pub trait ByRefArg {}
+
+    impl ByRefArg for CString {}
+    impl ByRefArg for &CStr {}
+    impl<T: CellValue> ByRefArg for SPRef<'_, T> {}
+    impl ByRefArg for SPString<'_> {}
+    impl<T: EnumStructField> ByRefArg for SPEnumStruct<'_, T> {}
+    impl ByRefArg for Vector {}
+    impl ByRefArg for [f32; 3] {}
+
+    /// A single variadic argument, see [`SPVarArgs`].
+    #[derive(Debug, Clone, Copy)]
+    pub struct SPVarArg<'a> {
+        ctx: &'a IPluginContext,
+        local_addr: cell_t,
+    }
+
+    impl<'a> SPVarArg<'a> {
+        pub fn local_addr(&self) -> cell_t {
+            self.local_addr
+        }
+
+        /// Converts the argument from the address it was passed by, see [`ByRefArg`].
+        pub fn get<T: ByRefArg + TryFromWithContext<'a, cell_t>>(&self) -> Result<T, T::Error> {
+            T::try_from_plugin(self.ctx, self.local_addr)
+        }
+
+        pub fn as_cell(&self) -> Result<cell_t, &'static str> {
+            let value: SPRef<cell_t> = self.get()?;
+            value.get().map_err(|e| e.message())
+        }
+
+        pub fn as_int(&self) -> Result<i32, &'static str> {
+            self.as_cell().map(i32::from)
+        }
+
+        pub fn as_float(&self) -> Result<f32, &'static str> {
+            self.as_cell().map(f32::from)
+        }
+
+        pub fn as_str(&self) -> Result<&'a str, &'static str> {
+            let s: &CStr = self.get()?;
+            s.to_str().map_err(|_| "Invalid UTF-8 string")
+        }
+
+        pub fn as_array<T>(&self, size: usize) -> Result<&'a [T], &'static str>
+        where
+            &'a [T]: TryFromWithContext<'a, SizedArg, Error = &'static str>,
+        {
+            let size = i32::try_from(size).map_err(|_| "Invalid array size")?;
+            <&'a [T]>::try_from_plugin(self.ctx, SizedArg { value: self.local_addr, size: size.into() })
+        }
+    }
+
+    #[repr(C)]
+    pub struct NativeInfo {
+        pub name: *const c_char,
+        pub func: Option<unsafe extern "C" fn(ctx: IPluginContextPtr, args: *const cell_t) -> cell_t>,
+    }
+
+    pub struct IdentityToken();
+    pub type IdentityTokenPtr = *mut IdentityToken;
+
+    pub type IExtensionInterfacePtr = *mut *mut IExtensionInterfaceVtable;
+    pub type IExtensionPtr = *mut *mut IExtensionVtable;
+    pub type SMInterfacePtr = *mut *mut SMInterfaceVtable;
+    pub type IShareSysPtr = *mut *mut IShareSysVtable;
+    pub type IFeatureProviderPtr = *mut *mut IFeatureProviderVtable;
+    pub type IPluginRuntimePtr = *mut *mut IPluginRuntimeVtable;
+    pub type IPluginContextPtr = *mut *mut IPluginContextVtable;
+    pub type ICallablePtr = *mut *mut ICallableVtable;
+    pub type IPluginFunctionPtr = *mut *mut IPluginFunctionVtable;
+    pub type IForwardManagerPtr = *mut *mut IForwardManagerVtable;
+    pub type IForwardPtr = *mut *mut IForwardVtable;
+    pub type IChangeableForwardPtr = *mut *mut IChangeableForwardVtable;
+    pub type IPluginManagerPtr = *mut *mut IPluginManagerVtable;
+    pub type IPluginsListenerPtr = *mut *mut IPluginsListenerVtable;
+    pub type IPluginPtr = *mut *mut IPluginVtable;
+    pub type IHandleSysPtr = *mut *mut IHandleSysVtable;
+    pub type IHandleTypeDispatchPtr = *mut *mut IHandleTypeDispatchVtable;
+    pub type ITimerSystemPtr = *mut *mut ITimerSystemVtable;
+    pub type ITimedEventPtr = *mut *mut ITimedEventVtable;
+    pub type ITimerPtr = *mut *mut ITimerVtable;
+    pub type ISourceModPtr = *mut *mut ISourceModVtable;
+    pub type IRootConsolePtr = *mut *mut IRootConsoleVtable;
+    pub type IThreaderPtr = *mut *mut IThreaderVtable;
+    pub type IThreadCreatorPtr = *mut *mut IThreadCreatorVtable;
+    pub type IThreadPtr = *mut *mut IThreadVtable;
+    pub type IThreadHandlePtr = *mut *mut IThreadHandleVtable;
+    pub type IMutexPtr = *mut *mut IMutexVtable;
+    pub type IEventSignalPtr = *mut *mut IEventSignalVtable;
+
+    pub type GameFrameHookFn = unsafe extern "C" fn(simulating: bool);
+    pub type FrameActionFn = unsafe extern "C" fn(data: *mut c_void);
+
+    pub type funcid_t = u32;
+
+    // SourcePawn cells are 32-bit on every platform, even where pointers are 64-bit.
+    // Check the layouts shared with SourceMod here so that a mismatch fails the build rather than crashing the server.
+    const _: () = assert!(std::mem::size_of::<cell_t>() == 4);
+    const _: () = assert!(std::mem::align_of::<cell_t>() == 4);
+    const _: () = assert!(std::mem::size_of::<IdentityType>() == 4);
+    const _: () = assert!(std::mem::size_of::<FeatureType>() == 1);
+    const _: () = assert!(std::mem::size_of::<FeatureStatus>() == 1);
+    const _: () = assert!(std::mem::size_of::<libc::size_t>() == std::mem::size_of::<usize>());
+    const _: () = assert!(std::mem::size_of::<NativeInfo>() == 2 * std::mem::size_of::<usize>());
+    const _: () = assert!(std::mem::size_of::<IPluginContextPtr>() == std::mem::size_of::<usize>());
+    const _: () = assert!(std::mem::size_of::<HandleError>() == 4);
+    const _: () = assert!(std::mem::size_of::<HandleSecurity>() == 2 * std::mem::size_of::<usize>());
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn with_native_call<R>(f: impl FnOnce() -> R) -> R {
+            begin_native_call();
+            let result = f();
+            end_native_call();
+            result
+        }
+
+        #[test]
+        fn shared_borrows_may_overlap() {
+            with_native_call(|| {
+                assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Shared).is_ok());
+                assert!(borrow_cells(cell_t::from(8), 16, CellAccess::Shared).is_ok());
+                assert!(borrow_cells(cell_t::from(4), 4, CellAccess::Copied).is_ok());
+            });
+        }
+
+        #[test]
+        fn exclusive_borrows_must_not_overlap() {
+            with_native_call(|| {
+                assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Exclusive).is_ok());
+                assert!(borrow_cells(cell_t::from(12), 4, CellAccess::Shared).is_err());
+                assert!(borrow_cells(cell_t::from(12), 4, CellAccess::Copied).is_err());
+                assert!(borrow_cells(cell_t::from(-4), 8, CellAccess::Exclusive).is_err());
+                assert!(borrow_cells(cell_t::from(16), 4, CellAccess::Exclusive).is_ok());
+                assert!(borrow_cells(cell_t::from(-4), 4, CellAccess::Exclusive).is_ok());
+            });
+
+            with_native_call(|| {
+                assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Shared).is_ok());
+                assert!(borrow_cells(cell_t::from(8), 4, CellAccess::Exclusive).is_err());
+            });
+        }
+
+        #[test]
+        fn empty_borrows_never_overlap() {
+            with_native_call(|| {
+                assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Exclusive).is_ok());
+                assert!(borrow_cells(cell_t::from(4), 0, CellAccess::Exclusive).is_ok());
+            });
+        }
+
+        #[test]
+        fn borrows_are_scoped_to_the_native_call() {
+            assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Exclusive).is_ok());
+            assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Exclusive).is_ok());
+
+            with_native_call(|| {
+                assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Exclusive).is_ok());
+
+                // A nested native call gets its own arguments.
+                with_native_call(|| assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Exclusive).is_ok()));
+
+                assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Exclusive).is_err());
+            });
+
+            with_native_call(|| assert!(borrow_cells(cell_t::from(0), 16, CellAccess::Exclusive).is_ok()));
+        }
+
+        #[test]
+        fn writes_must_not_change_shared_borrows() {
+            with_native_call(|| {
+                assert!(borrow_cells(cell_t::from(0), 8, CellAccess::Shared).is_ok());
+                assert!(borrow_cells(cell_t::from(8), 8, CellAccess::Copied).is_ok());
+                assert_eq!(check_cells_writable(cell_t::from(4), 4), Err(SPError::MEMACCESS));
+                assert_eq!(check_cells_writable(cell_t::from(8), 8), Ok(()));
+            });
+        }
+    }
+}
+
+// Vtables for interfaces we implement (listeners, dispatches, threads) are filled in by the adapters further down, so
+// every slot in them is public.
+//
+// MSVC groups overloaded methods together in a vtable, in reverse declaration order, where GCC keeps declaration order.
+// Overloads that end up in different places are given a #[cfg(windows)] and a #[cfg(not(windows))] slot.
+mod vtables {
+    use super::types::*;
+
+    use libc::size_t;
+    use sm_ext_derive::vtable;
+    use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+    #[vtable(IExtensionInterfacePtr)]
+    pub struct IExtensionInterfaceVtable {
+        pub GetExtensionVersion: fn() -> i32,
+        pub OnExtensionLoad: fn(me: IExtensionPtr, sys: IShareSysPtr, error: *mut c_char, maxlength: size_t, late: bool) -> bool,
+        pub OnExtensionUnload: fn() -> (),
+        pub OnExtensionsAllLoaded: fn() -> (),
+        pub OnExtensionPauseChange: fn(pause: bool) -> (),
+        pub QueryInterfaceDrop: fn(interface: SMInterfacePtr) -> bool,
+        pub NotifyInterfaceDrop: fn(interface: SMInterfacePtr) -> (),
+        pub QueryRunning: fn(error: *mut c_char, maxlength: size_t) -> bool,
+        pub IsMetamodExtension: fn() -> bool,
+        pub GetExtensionName: fn() -> *const c_char,
+        pub GetExtensionURL: fn() -> *const c_char,
+        pub GetExtensionTag: fn() -> *const c_char,
+        pub GetExtensionAuthor: fn() -> *const c_char,
+        pub GetExtensionVerString: fn() -> *const c_char,
+        pub GetExtensionDescription: fn() -> *const c_char,
+        pub GetExtensionDateString: fn() -> *const c_char,
+        pub OnCoreMapStart: fn(edict_list: *mut c_void, edict_count: c_int, client_max: c_int) -> (),
+        pub OnDependenciesDropped: fn() -> (),
+        pub OnCoreMapEnd: fn() -> (),
+    }
+
+    #[vtable(IExtensionPtr, wrapper = IExtension)]
+    pub struct IExtensionVtable {
+        pub IsLoaded: fn() -> bool,
+        pub GetAPI: fn() -> IExtensionInterfacePtr,
+        pub GetFilename: fn() -> *const c_char,
+        pub GetIdentity: fn() -> IdentityTokenPtr,
+        _FindFirstDependency: fn() -> *mut c_void,
+        _FindNextDependency: fn() -> *mut c_void,
+        _FreeDependencyIterator: fn() -> *mut c_void,
+        #[wrapper(skip)]
+        pub IsRunning: fn(error: *mut c_char, maxlength: size_t) -> bool,
+        pub IsExternal: fn() -> bool,
+    }
+
+    #[vtable(SMInterfacePtr, wrapper = SMInterface)]
+    pub struct SMInterfaceVtable {
+        pub GetInterfaceVersion: fn() -> c_uint,
+        pub GetInterfaceName: fn() -> *const c_char,
+        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+    }
+
+    #[vtable(IShareSysPtr, wrapper = IShareSys)]
+    pub struct IShareSysVtable {
+        pub AddInterface: fn(myself: IExtensionPtr, iface: SMInterfacePtr) -> bool,
+        #[wrapper(skip)]
+        pub RequestInterface: fn(iface_name: *const c_char, iface_vers: c_uint, myself: IExtensionPtr, iface: *mut SMInterfacePtr) -> bool,
+        #[wrapper(skip)]
+        pub AddNatives: fn(myself: IExtensionPtr, natives: *const NativeInfo) -> (),
+        pub CreateIdentType: fn(name: *const c_char) -> IdentityType,
+        pub FindIdentType: fn(name: *const c_char) -> IdentityType,
+        pub CreateIdentity: fn(ident_type: IdentityType, ptr: *mut c_void) -> IdentityTokenPtr,
+        pub DestroyIdentType: fn(ident_type: IdentityType) -> (),
+        pub DestroyIdentity: fn(identity: IdentityTokenPtr) -> (),
+        pub AddDependency: fn(myself: IExtensionPtr, filename: *const c_char, require: bool, autoload: bool) -> (),
+        pub RegisterLibrary: fn(myself: IExtensionPtr, name: *const c_char) -> (),
+        _OverrideNatives: fn(myself: IExtensionPtr, natives: *const NativeInfo) -> (),
+        pub AddCapabilityProvider: fn(myself: IExtensionPtr, provider: IFeatureProviderPtr, name: *const c_char) -> (),
+        pub DropCapabilityProvider: fn(myself: IExtensionPtr, provider: IFeatureProviderPtr, name: *const c_char) -> (),
+        pub TestFeature: fn(rt: IPluginRuntimePtr, feature_type: FeatureType, name: *const c_char) -> FeatureStatus,
+    }
+
+    #[vtable(IFeatureProviderPtr)]
+    pub struct IFeatureProviderVtable {}
+
+    #[vtable(IPluginRuntimePtr)]
+    pub struct IPluginRuntimeVtable {}
+
+    #[vtable(IPluginPtr)]
+    pub struct IPluginVtable {
+        _Destructor: fn() -> (),
+        #[cfg(unix)]
+        _Destructor2: fn() -> (),
+        _GetType: fn(),
+        pub GetBaseContext: fn() -> IPluginContextPtr,
+    }
+
+    #[vtable(IPluginManagerPtr, wrapper = IPluginManager)]
+    pub struct IPluginManagerVtable {
+        pub GetInterfaceVersion: fn() -> c_uint,
+        pub GetInterfaceName: fn() -> *const c_char,
+        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+        _LoadPlugin: fn(),
+        _UnloadPlugin: fn(),
+        _FindPluginByContext: fn(),
+        pub GetPluginCount: fn() -> c_uint,
+        _GetPluginIterator: fn(),
+        #[wrapper(skip)]
+        pub AddPluginsListener: fn(listener: IPluginsListenerPtr) -> (),
+        #[wrapper(skip)]
+        pub RemovePluginsListener: fn(listener: IPluginsListenerPtr) -> (),
+    }
+
+    #[vtable(IPluginsListenerPtr)]
+    pub struct IPluginsListenerVtable {
+        pub OnPluginCreated: fn(plugin: IPluginPtr) -> (),
+        pub OnPluginLoaded: fn(plugin: IPluginPtr) -> (),
+        pub OnPluginPauseChange: fn(plugin: IPluginPtr, paused: bool) -> (),
+        pub OnPluginUnloaded: fn(plugin: IPluginPtr) -> (),
+        pub OnPluginDestroyed: fn(plugin: IPluginPtr) -> (),
+        pub OnPluginWillUnload: fn(plugin: IPluginPtr) -> (),
+        pub GetApiVersion: fn() -> c_uint,
+    }
+
+    #[vtable(IPluginContextPtr, wrapper = IPluginContext)]
+    pub struct IPluginContextVtable {
+        _Destructor: fn() -> (),
+        #[cfg(unix)]
+        _Destructor2: fn() -> (),
+        _GetVirtualMachine: fn(),
+        _GetContext: fn(),
+        _IsDebugging: fn(),
+        _SetDebugBreak: fn(),
+        _GetDebugInfo: fn(),
+        #[wrapper(skip)]
+        pub HeapAlloc: fn(cells: c_uint, local_addr: *mut cell_t, phys_addr: *mut *mut cell_t) -> c_int,
+        _HeapPop: fn(local_addr: cell_t) -> c_int,
+        #[wrapper(skip)]
+        pub HeapRelease: fn(local_addr: cell_t) -> c_int,
+        _FindNativeByName: fn(),
         _GetNativeByIndex: fn(),
         _GetNativesNum: fn(),
         _FindPublicByName: fn(),
@@ -258,10 +1512,13 @@ pub(self) mod vtables {
         _FindPubvarByName: fn(),
         _GetPubvarAddrs: fn(),
         _GetPubVarsNum: fn(),
+        #[wrapper(skip)]
         pub LocalToPhysAddr: fn(local_addr: cell_t, phys_addr: *mut *mut cell_t) -> c_int,
+        #[wrapper(skip)]
         pub LocalToString: fn(local_addr: cell_t, addr: *mut *mut c_char) -> c_int,
         _StringToLocal: fn(),
-        _StringToLocalUTF8: fn(),
+        #[wrapper(skip)]
+        pub StringToLocalUTF8: fn(local_addr: cell_t, maxbytes: size_t, source: *const c_char, wrtnbytes: *mut size_t) -> c_int,
         _PushCell: fn(),
         _PushCellArray: fn(),
         _PushString: fn(),
@@ -272,11 +1529,15 @@ pub(self) mod vtables {
         _Execute: fn(),
         _ThrowNativeErrorEx: fn(),
         pub ThrowNativeError: fn(*const c_char, ...) -> cell_t,
-        _GetFunctionByName: fn(),
-        _GetFunctionById: fn(),
-        _GetIdentity: fn(),
-        _GetNullRef: fn(),
-        _LocalToStringNULL: fn(),
+        #[wrapper(skip)]
+        pub GetFunctionByName: fn(public_name: *const c_char) -> IPluginFunctionPtr,
+        #[wrapper(skip)]
+        pub GetFunctionById: fn(func_id: funcid_t) -> IPluginFunctionPtr,
+        pub GetIdentity: fn() -> IdentityTokenPtr,
+        #[wrapper(skip)]
+        pub GetNullRef: fn(null_type: SPNullType) -> *mut cell_t,
+        #[wrapper(skip)]
+        pub LocalToStringNULL: fn(local_addr: cell_t, addr: *mut *mut c_char) -> c_int,
         _BindNativeToIndex: fn(),
         _IsInExec: fn(),
         _GetRuntime: fn(),
@@ -296,54 +1557,2349 @@ pub(self) mod vtables {
         _CreateFrameIterator: fn(),
         _DestroyFrameIterator: fn(),
     }
+
+    #[vtable(ICallablePtr)]
+    pub struct ICallableVtable {
+        pub PushCell: fn(cell: cell_t) -> c_int,
+        pub PushCellByRef: fn(cell: *mut cell_t, flags: c_int) -> c_int,
+        pub PushFloat: fn(number: f32) -> c_int,
+        pub PushFloatByRef: fn(number: *mut f32, flags: c_int) -> c_int,
+        pub PushArray: fn(inarray: *mut cell_t, cells: c_uint, flags: c_int) -> c_int,
+        pub PushString: fn(string: *const c_char) -> c_int,
+        pub PushStringEx: fn(buffer: *mut c_char, length: size_t, sz_flags: c_int, cp_flags: c_int) -> c_int,
+        pub Cancel: fn() -> (),
+    }
+
+    // Includes the methods inherited from ICallable.
+    #[vtable(IPluginFunctionPtr, wrapper = IPluginFunction)]
+    pub struct IPluginFunctionVtable {
+        #[wrapper(skip)]
+        pub PushCell: fn(cell: cell_t) -> c_int,
+        #[wrapper(skip)]
+        pub PushCellByRef: fn(cell: *mut cell_t, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushFloat: fn(number: f32) -> c_int,
+        #[wrapper(skip)]
+        pub PushFloatByRef: fn(number: *mut f32, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushArray: fn(inarray: *mut cell_t, cells: c_uint, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushString: fn(string: *const c_char) -> c_int,
+        #[wrapper(skip)]
+        pub PushStringEx: fn(buffer: *mut c_char, length: size_t, sz_flags: c_int, cp_flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub Cancel: fn() -> (),
+        #[wrapper(skip)]
+        pub Execute: fn(result: *mut cell_t) -> c_int,
+        _CallFunction: fn(),
+        #[wrapper(skip)]
+        pub GetParentContext: fn() -> IPluginContextPtr,
+        pub IsRunnable: fn() -> bool,
+        pub GetFunctionID: fn() -> funcid_t,
+        _Execute2: fn(),
+        _CallFunction2: fn(),
+        _GetParentRuntime: fn(),
+        _Invoke: fn(),
+        pub DebugName: fn() -> *const c_char,
+    }
+
+    #[vtable(IForwardManagerPtr, wrapper = IForwardManager)]
+    pub struct IForwardManagerVtable {
+        pub GetInterfaceVersion: fn() -> c_uint,
+        pub GetInterfaceName: fn() -> *const c_char,
+        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+        #[wrapper(skip)]
+        pub CreateForward: fn(name: *const c_char, et: ExecType, num_params: c_uint, types: *const ParamType, ...) -> IForwardPtr,
+        #[wrapper(skip)]
+        pub CreateForwardEx: fn(name: *const c_char, et: ExecType, num_params: c_int, types: *const ParamType, ...) -> IChangeableForwardPtr,
+        _FindForward: fn(),
+        #[wrapper(skip)]
+        pub ReleaseForward: fn(forward: IForwardPtr) -> (),
+    }
+
+    // Includes the methods inherited from ICallable.
+    #[vtable(IForwardPtr, wrapper = IForward)]
+    pub struct IForwardVtable {
+        #[wrapper(skip)]
+        pub PushCell: fn(cell: cell_t) -> c_int,
+        #[wrapper(skip)]
+        pub PushCellByRef: fn(cell: *mut cell_t, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushFloat: fn(number: f32) -> c_int,
+        #[wrapper(skip)]
+        pub PushFloatByRef: fn(number: *mut f32, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushArray: fn(inarray: *mut cell_t, cells: c_uint, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushString: fn(string: *const c_char) -> c_int,
+        #[wrapper(skip)]
+        pub PushStringEx: fn(buffer: *mut c_char, length: size_t, sz_flags: c_int, cp_flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub Cancel: fn() -> (),
+        _Destructor: fn() -> (),
+        #[cfg(unix)]
+        _Destructor2: fn() -> (),
+        pub GetForwardName: fn() -> *const c_char,
+        pub GetFunctionCount: fn() -> c_uint,
+        pub GetExecType: fn() -> ExecType,
+        #[wrapper(skip)]
+        pub Execute: fn(result: *mut cell_t, filter: *mut c_void) -> c_int,
+    }
+
+    // Includes the methods inherited from ICallable and IForward.
+    #[vtable(IChangeableForwardPtr, wrapper = IChangeableForward)]
+    pub struct IChangeableForwardVtable {
+        #[wrapper(skip)]
+        pub PushCell: fn(cell: cell_t) -> c_int,
+        #[wrapper(skip)]
+        pub PushCellByRef: fn(cell: *mut cell_t, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushFloat: fn(number: f32) -> c_int,
+        #[wrapper(skip)]
+        pub PushFloatByRef: fn(number: *mut f32, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushArray: fn(inarray: *mut cell_t, cells: c_uint, flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub PushString: fn(string: *const c_char) -> c_int,
+        #[wrapper(skip)]
+        pub PushStringEx: fn(buffer: *mut c_char, length: size_t, sz_flags: c_int, cp_flags: c_int) -> c_int,
+        #[wrapper(skip)]
+        pub Cancel: fn() -> (),
+        _Destructor: fn() -> (),
+        #[cfg(unix)]
+        _Destructor2: fn() -> (),
+        pub GetForwardName: fn() -> *const c_char,
+        pub GetFunctionCount: fn() -> c_uint,
+        pub GetExecType: fn() -> ExecType,
+        #[wrapper(skip)]
+        pub Execute: fn(result: *mut cell_t, filter: *mut c_void) -> c_int,
+        #[cfg(windows)]
+        _RemoveFunctionById: fn(),
+        #[wrapper(skip)]
+        pub RemoveFunction: fn(func: IPluginFunctionPtr) -> bool,
+        #[wrapper(skip)]
+        pub RemoveFunctionsOfPlugin: fn(plugin: IPluginPtr) -> c_uint,
+        #[cfg(windows)]
+        _AddFunctionById: fn(),
+        #[wrapper(skip)]
+        pub AddFunction: fn(func: IPluginFunctionPtr) -> bool,
+        #[cfg(not(windows))]
+        _AddFunctionById: fn(),
+        #[cfg(not(windows))]
+        _RemoveFunctionById: fn(),
+    }
+
+    #[vtable(IHandleSysPtr, wrapper = IHandleSys)]
+    pub struct IHandleSysVtable {
+        pub GetInterfaceVersion: fn() -> c_uint,
+        pub GetInterfaceName: fn() -> *const c_char,
+        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+        #[wrapper(skip)]
+        pub CreateType: fn(name: *const c_char, dispatch: IHandleTypeDispatchPtr, parent: HandleType_t, type_access: *const TypeAccess, handle_access: *const HandleAccess, ident: IdentityTokenPtr, err: *mut HandleError) -> HandleType_t,
+        #[wrapper(skip)]
+        pub RemoveType: fn(handle_type: HandleType_t, ident: IdentityTokenPtr) -> bool,
+        #[wrapper(skip)]
+        pub FindHandleType: fn(name: *const c_char, handle_type: *mut HandleType_t) -> bool,
+        #[wrapper(skip)]
+        pub CreateHandle: fn(handle_type: HandleType_t, object: *mut c_void, owner: IdentityTokenPtr, ident: IdentityTokenPtr, err: *mut HandleError) -> Handle_t,
+        #[wrapper(skip)]
+        pub FreeHandle: fn(handle: Handle_t, security: *const HandleSecurity) -> HandleError,
+        #[wrapper(skip)]
+        pub CloneHandle: fn(handle: Handle_t, new_handle: *mut Handle_t, new_owner: IdentityTokenPtr, security: *const HandleSecurity) -> HandleError,
+        #[wrapper(skip)]
+        pub ReadHandle: fn(handle: Handle_t, handle_type: HandleType_t, security: *const HandleSecurity, object: *mut *mut c_void) -> HandleError,
+        #[wrapper(skip)]
+        pub InitAccessDefaults: fn(type_access: *mut TypeAccess, handle_access: *mut HandleAccess) -> bool,
+        #[wrapper(skip)]
+        pub CreateHandleEx: fn(handle_type: HandleType_t, object: *mut c_void, security: *const HandleSecurity, access: *const HandleAccess, err: *mut HandleError) -> Handle_t,
+        pub TypeCheck: fn(given: HandleType_t, actual: HandleType_t) -> bool,
+    }
+
+    #[vtable(ISourceModPtr, wrapper = ISourceMod)]
+    pub struct ISourceModVtable {
+        pub GetInterfaceVersion: fn() -> c_uint,
+        pub GetInterfaceName: fn() -> *const c_char,
+        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+        pub GetGamePath: fn() -> *const c_char,
+        pub GetSourceModPath: fn() -> *const c_char,
+        #[wrapper(skip)]
+        pub BuildPath: fn(path_type: PathType, buffer: *mut c_char, maxlength: size_t, format: *const c_char, ...) -> size_t,
+        #[wrapper(skip)]
+        pub LogMessage: fn(ext: IExtensionPtr, format: *const c_char, ...) -> (),
+        #[wrapper(skip)]
+        pub LogError: fn(ext: IExtensionPtr, format: *const c_char, ...) -> (),
+        _FormatString: fn(),
+        _CreateDataPack: fn(),
+        _FreeDataPack: fn(),
+        _GetDataPackHandleType: fn(),
+        _ReadKeyValuesHandle: fn(),
+        pub GetGameFolderName: fn() -> *const c_char,
+        _GetScriptingEngine: fn(),
+        _GetScriptingVM: fn(),
+        _GetAdjustedTime: fn(),
+        _SetGlobalTarget: fn(),
+        _GetGlobalTarget: fn(),
+        pub AddGameFrameHook: fn(hook: GameFrameHookFn) -> (),
+        pub RemoveGameFrameHook: fn(hook: GameFrameHookFn) -> (),
+        _Format: fn(),
+        _FormatArgs: fn(),
+        pub AddFrameAction: fn(action: FrameActionFn, data: *mut c_void) -> (),
+        _GetCoreConfigValue: fn(),
+        _GetPluginId: fn(),
+        _GetShApiVersion: fn(),
+        pub IsMapRunning: fn() -> bool,
+        _FromPseudoAddress: fn(),
+        _ToPseudoAddress: fn(),
+    }
+
+    #[vtable(IRootConsolePtr, wrapper = IRootConsole)]
+    pub struct IRootConsoleVtable {
+        pub GetInterfaceVersion: fn() -> c_uint,
+        pub GetInterfaceName: fn() -> *const c_char,
+        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+        _AddRootConsoleCommand: fn(),
+        _RemoveRootConsoleCommand: fn(),
+        #[wrapper(skip)]
+        pub ConsolePrint: fn(format: *const c_char, ...) -> (),
+        _DrawGenericOption: fn(),
+        _AddRootConsoleCommand2: fn(),
+        _AddRootConsoleCommand3: fn(),
+    }
+
+    // IThreader also inherits from IThreadCreator, whose methods are on a second vtable after this one, see IThreadCreatorVtable.
+    #[vtable(IThreaderPtr, wrapper = IThreader)]
+    pub struct IThreaderVtable {
+        pub GetInterfaceVersion: fn() -> c_uint,
+        pub GetInterfaceName: fn() -> *const c_char,
+        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+        #[wrapper(skip)]
+        pub MakeMutex: fn() -> IMutexPtr,
+        pub ThreadSleep: fn(ms: c_uint) -> (),
+        #[wrapper(skip)]
+        pub MakeEventSignal: fn() -> IEventSignalPtr,
+        _MakeWorker: fn(),
+        _DestroyWorker: fn(),
+    }
+
+    // The MakeThread overload we use ends up between the other two on every platform.
+    #[vtable(IThreadCreatorPtr)]
+    pub struct IThreadCreatorVtable {
+        _Destructor: fn() -> (),
+        #[cfg(unix)]
+        _Destructor2: fn() -> (),
+        _MakeThreadDefault: fn(),
+        pub MakeThread: fn(thread: IThreadPtr, flags: ThreadFlags) -> IThreadHandlePtr,
+        _MakeThreadWithParams: fn(),
+        _GetPriorityBounds: fn(),
+    }
+
+    #[vtable(IThreadPtr)]
+    pub struct IThreadVtable {
+        pub Destructor: fn() -> (),
+        #[cfg(unix)]
+        pub Destructor2: fn() -> (),
+        pub RunThread: fn(handle: IThreadHandlePtr) -> (),
+        pub OnTerminate: fn(handle: IThreadHandlePtr, cancel: bool) -> (),
+    }
+
+    #[vtable(IThreadHandlePtr)]
+    pub struct IThreadHandleVtable {
+        _Destructor: fn() -> (),
+        #[cfg(unix)]
+        _Destructor2: fn() -> (),
+        pub WaitForThread: fn() -> bool,
+        pub DestroyThis: fn() -> (),
+        _Parent: fn(),
+        _GetParams: fn(),
+        _GetPriority: fn(),
+        _SetPriority: fn(),
+        _GetState: fn(),
+        _Unpause: fn(),
+    }
+
+    #[vtable(IMutexPtr, wrapper = IMutex)]
+    pub struct IMutexVtable {
+        _Destructor: fn() -> (),
+        #[cfg(unix)]
+        _Destructor2: fn() -> (),
+        #[wrapper(skip)]
+        pub TryLock: fn() -> bool,
+        #[wrapper(skip)]
+        pub Lock: fn() -> (),
+        #[wrapper(skip)]
+        pub Unlock: fn() -> (),
+        #[wrapper(skip)]
+        pub DestroyThis: fn() -> (),
+    }
+
+    #[vtable(IEventSignalPtr, wrapper = IEventSignal)]
+    pub struct IEventSignalVtable {
+        _Destructor: fn() -> (),
+        #[cfg(unix)]
+        _Destructor2: fn() -> (),
+        pub Wait: fn() -> (),
+        pub Signal: fn() -> (),
+        #[wrapper(skip)]
+        pub DestroyThis: fn() -> (),
+    }
+
+    #[vtable(ITimerSystemPtr, wrapper = ITimerSystem)]
+    pub struct ITimerSystemVtable {
+        pub GetInterfaceVersion: fn() -> c_uint,
+        pub GetInterfaceName: fn() -> *const c_char,
+        pub IsVersionCompatible: fn(version: c_uint) -> bool,
+        #[wrapper(skip)]
+        pub CreateTimer: fn(callbacks: ITimedEventPtr, interval: f32, data: *mut c_void, flags: TimerFlags) -> ITimerPtr,
+        #[wrapper(skip)]
+        pub KillTimer: fn(timer: ITimerPtr) -> (),
+        #[wrapper(skip)]
+        pub FireTimerOnce: fn(timer: ITimerPtr, delay_exec: bool) -> (),
+        _MapChange: fn(),
+        _SetMapTimer: fn(),
+        _NotifyOfGameStart: fn(),
+        pub GetTickedTime: fn() -> f32,
+    }
+
+    #[vtable(ITimerPtr)]
+    pub struct ITimerVtable {}
+
+    #[vtable(ITimedEventPtr)]
+    pub struct ITimedEventVtable {
+        pub OnTimer: fn(timer: ITimerPtr, data: *mut c_void) -> ResultType,
+        pub OnTimerEnd: fn(timer: ITimerPtr, data: *mut c_void) -> (),
+    }
+
+    #[vtable(IHandleTypeDispatchPtr)]
+    pub struct IHandleTypeDispatchVtable {
+        pub GetDispatchVersion: fn() -> c_uint,
+        pub OnHandleDestroy: fn(handle_type: HandleType_t, object: *mut c_void) -> (),
+        pub GetHandleApproxSize: fn(handle_type: HandleType_t, object: *mut c_void, size: *mut c_uint) -> bool,
+    }
+}
+
+pub use IExtensionInterfaceApi::*;
+mod IExtensionInterfaceApi {
+    use super::types::{IExtensionPtr, IShareSysPtr, SMInterfacePtr};
+    use super::vtables::IExtensionInterfaceVtable;
+    use super::{IExtension, IShareSys, SMInterface};
+
+    use libc::size_t;
+    use sm_ext_derive::vtable_adapter;
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int, c_void};
+
+    pub trait IExtensionInterface {
+        fn on_extension_load(&mut self, me: IExtension, sys: IShareSys, late: bool) -> Result<(), CString> {
+            Ok(())
+        }
+        fn on_extension_unload(&mut self) {}
+        fn on_extensions_all_loaded(&mut self) {}
+        fn on_extension_pause_change(&mut self, pause: bool) {}
+        fn on_core_map_start(&mut self, edict_list: *mut c_void, edict_count: i32, client_max: i32) {}
+        fn on_core_map_end(&mut self) {}
+        fn query_interface_drop(&mut self, interface: SMInterface) -> bool {
+            false
+        }
+        fn notify_interface_drop(&mut self, interface: SMInterface) {}
+        fn query_running(&mut self) -> Result<(), CString> {
+            Ok(())
+        }
+        fn on_dependencies_dropped(&mut self) {}
+    }
+
+    pub trait IExtensionMetadata {
+        fn get_extension_name(&self) -> &'static CStr;
+        fn get_extension_url(&self) -> &'static CStr;
+        fn get_extension_tag(&self) -> &'static CStr;
+        fn get_extension_author(&self) -> &'static CStr;
+        fn get_extension_ver_string(&self) -> &'static CStr;
+        fn get_extension_description(&self) -> &'static CStr;
+        fn get_extension_date_string(&self) -> &'static CStr;
+    }
+
+    #[repr(C)]
+    pub struct IExtensionInterfaceAdapter<T: IExtensionInterface + IExtensionMetadata> {
+        vtable: *mut IExtensionInterfaceVtable,
+        delegate: T,
+    }
+
+    impl<T: IExtensionInterface + IExtensionMetadata> Drop for IExtensionInterfaceAdapter<T> {
+        fn drop(&mut self) {
+            unsafe {
+                drop(Box::from_raw(self.vtable));
+            }
+        }
+    }
+
+    impl<T: IExtensionInterface + IExtensionMetadata> IExtensionInterfaceAdapter<T> {
+        pub fn new(delegate: T) -> IExtensionInterfaceAdapter<T> {
+            IExtensionInterfaceAdapter { vtable: Box::into_raw(Box::new(Self::vtable())), delegate }
+        }
+    }
+
+    #[vtable_adapter(IExtensionInterfaceVtable)]
+    impl<T: IExtensionInterface + IExtensionMetadata> IExtensionInterfaceAdapter<T> {
+        fn get_extension_version(&mut self) -> i32 {
+            8
+        }
+
+        unsafe fn on_extension_load(&mut self, me: IExtensionPtr, sys: IShareSysPtr, error: *mut c_char, maxlength: size_t, late: bool) -> bool {
+            match self.delegate.on_extension_load(IExtension(me), IShareSys(sys), late) {
+                Ok(_) => true,
+                Err(str) => {
+                    libc::strncpy(error, str.as_ptr(), maxlength);
+                    false
+                }
+            }
+        }
+
+        fn on_extension_unload(&mut self) {
+            self.delegate.on_extension_unload();
+
+            // Queued tasks, timers and handle types go first, as their callbacks and values could hold handles or forwards.
+            // Closing the dispatch queue first stops finished jobs from queuing their results while their threads are joined.
+            // Forwards and handle types still registered are released here, so wrappers dropped later leave them alone.
+            super::ISourceModApi::close_dispatch_queue();
+            super::IThreaderApi::join_all_jobs();
+            super::executor::stop();
+            super::ITimerSystemApi::kill_all_timers();
+            super::IHandleSysApi::remove_all_handle_types();
+            super::IForwardManagerApi::release_all_forwards();
+            super::logger::uninstall();
+        }
+
+        fn on_extensions_all_loaded(&mut self) {
+            self.delegate.on_extensions_all_loaded()
+        }
+
+        fn on_extension_pause_change(&mut self, pause: bool) {
+            self.delegate.on_extension_pause_change(pause)
+        }
+
+        fn query_interface_drop(&mut self, interface: SMInterfacePtr) -> bool {
+            self.delegate.query_interface_drop(SMInterface(interface))
+        }
+
+        fn notify_interface_drop(&mut self, interface: SMInterfacePtr) {
+            self.delegate.notify_interface_drop(SMInterface(interface))
+        }
+
+        unsafe fn query_running(&mut self, error: *mut c_char, maxlength: size_t) -> bool {
+            match self.delegate.query_running() {
+                Ok(_) => true,
+                Err(str) => {
+                    libc::strncpy(error, str.as_ptr(), maxlength);
+                    false
+                }
+            }
+        }
+
+        fn is_metamod_extension(&mut self) -> bool {
+            false
+        }
+
+        fn get_extension_name(&mut self) -> *const c_char {
+            self.delegate.get_extension_name().as_ptr()
+        }
+
+        #[vtable_slot(GetExtensionURL)]
+        fn get_extension_url(&mut self) -> *const c_char {
+            self.delegate.get_extension_url().as_ptr()
+        }
+
+        fn get_extension_tag(&mut self) -> *const c_char {
+            self.delegate.get_extension_tag().as_ptr()
+        }
+
+        fn get_extension_author(&mut self) -> *const c_char {
+            self.delegate.get_extension_author().as_ptr()
+        }
+
+        fn get_extension_ver_string(&mut self) -> *const c_char {
+            self.delegate.get_extension_ver_string().as_ptr()
+        }
+
+        fn get_extension_description(&mut self) -> *const c_char {
+            self.delegate.get_extension_description().as_ptr()
+        }
+
+        fn get_extension_date_string(&mut self) -> *const c_char {
+            self.delegate.get_extension_date_string().as_ptr()
+        }
+
+        fn on_core_map_start(&mut self, edict_list: *mut c_void, edict_count: c_int, client_max: c_int) {
+            self.delegate.on_core_map_start(edict_list, edict_count, client_max)
+        }
+
+        fn on_dependencies_dropped(&mut self) {
+            self.delegate.on_dependencies_dropped()
+        }
+
+        fn on_core_map_end(&mut self) {
+            self.delegate.on_core_map_end();
+
+            super::ITimerSystemApi::kill_map_change_timers();
+        }
+    }
+}
+
+pub use IExtensionApi::*;
+mod IExtensionApi {
+    pub use super::vtables::IExtension;
+
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+    use std::str::Utf8Error;
+
+    #[derive(Debug)]
+    pub enum IsRunningError<'a> {
+        WithReason(&'a str),
+        InvalidReason(Utf8Error),
+    }
+
+    impl IExtension {
+        pub fn is_running(&self) -> Result<(), IsRunningError<'_>> {
+            unsafe {
+                let mut c_error = [0 as c_char; 256];
+                let result = ((**self.0).IsRunning)(self.0, c_error.as_mut_ptr(), c_error.len());
+
+                if result {
+                    Ok(())
+                } else {
+                    match CStr::from_ptr(c_error.as_ptr()).to_str() {
+                        Ok(error) => Err(IsRunningError::WithReason(error)),
+                        Err(e) => Err(IsRunningError::InvalidReason(e)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub use SMInterfaceApi::*;
+mod SMInterfaceApi {
+    pub use super::vtables::SMInterface;
+}
+
+pub use IShareSysApi::*;
+mod IShareSysApi {
+    pub use super::vtables::IShareSys;
+
+    use super::types::{NativeInfo, SMInterfacePtr};
+    use super::IExtensionApi::IExtension;
+    use super::SMInterfaceApi::SMInterface;
+
+    use std::ffi::{CString, NulError};
+    use std::ptr::null_mut;
+
+    #[derive(Debug)]
+    pub enum RequestInterfaceError {
+        StringError(NulError),
+        InterfaceError(),
+    }
+
+    /// An interface that can be requested from [`IShareSys`] by type, with [`IShareSys::request_typed_interface`].
+    pub trait RequestableInterface: Sized {
+        fn get_interface_name() -> &'static str;
+        fn get_interface_version() -> u32;
+
+        /// # Safety
+        ///
+        /// The interface must be the one named by `get_interface_name`, at a compatible version.
+        unsafe fn from_raw_interface(iface: SMInterface) -> Self;
+    }
+
+    impl IShareSys {
+        pub fn request_typed_interface<I: RequestableInterface>(&self, myself: &IExtension) -> Result<I, RequestInterfaceError> {
+            let iface = self.request_interface(myself, I::get_interface_name(), I::get_interface_version())?;
+
+            unsafe { Ok(I::from_raw_interface(iface)) }
+        }
+
+        pub fn request_interface(&self, myself: &IExtension, name: &str, version: u32) -> Result<SMInterface, RequestInterfaceError> {
+            let c_name = CString::new(name).map_err(RequestInterfaceError::StringError)?;
+
+            unsafe {
+                let mut iface: SMInterfacePtr = null_mut();
+                let res = ((**self.0).RequestInterface)(self.0, c_name.as_ptr(), version, myself.0, &mut iface);
+
+                if res {
+                    Ok(SMInterface(iface))
+                } else {
+                    Err(RequestInterfaceError::InterfaceError())
+                }
+            }
+        }
+
+        /// # Safety
+        ///
+        /// This is should be be used via the `register_natives!` macro only.
+        pub unsafe fn add_natives(&self, myself: &IExtension, natives: *const NativeInfo) {
+            ((**self.0).AddNatives)(self.0, myself.0, natives)
+        }
+    }
+}
+
+pub use IPluginContextApi::*;
+mod IPluginContextApi {
+    pub use super::vtables::IPluginContext;
+
+    use super::types::{cell_t, funcid_t, IPluginContextPtr, SPError, SPNullType};
+    use super::IPluginFunction;
+    use c_str_macro::c_str;
+    use std::cell::RefCell;
+    use std::convert::TryFrom;
+    use std::ffi::{CStr, CString};
+    use std::ops::{Deref, DerefMut};
+    use std::os::raw::c_char;
+    use std::ptr::null_mut;
+
+    impl IPluginContext {
+        pub fn throw_native_error(&self, err: String) -> cell_t {
+            let fmt = c_str!("%s");
+            let err = CString::new(err).unwrap_or_else(|_| c_str!("ThrowNativeError message contained NUL byte").into());
+            unsafe { ((**self.0).ThrowNativeError)(self.0, fmt.as_ptr(), err.as_ptr()) }
+        }
+
+        #[allow(clippy::mut_from_ref)]
+        pub fn local_to_phys_addr(&self, local: cell_t) -> Result<&mut cell_t, SPError> {
+            unsafe {
+                let mut addr: *mut cell_t = null_mut();
+                let res = ((**self.0).LocalToPhysAddr)(self.0, local, &mut addr);
+
+                if res == 0 {
+                    Ok(&mut *addr)
+                } else {
+                    Err(SPError(res))
+                }
+            }
+        }
+
+        pub fn local_to_string(&self, local: cell_t) -> Result<&CStr, SPError> {
+            unsafe {
+                let mut addr: *mut c_char = null_mut();
+                let res = ((**self.0).LocalToString)(self.0, local, &mut addr);
+
+                if res == 0 {
+                    Ok(CStr::from_ptr(addr))
+                } else {
+                    Err(SPError(res))
+                }
+            }
+        }
+
+        pub fn get_function_by_id(&self, func_id: funcid_t) -> Option<IPluginFunction> {
+            let function = unsafe { ((**self.0).GetFunctionById)(self.0, func_id) };
+
+            if function.is_null() {
+                None
+            } else {
+                Some(IPluginFunction(function))
+            }
+        }
+
+        pub fn get_function_by_name(&self, public_name: &CStr) -> Option<IPluginFunction> {
+            let function = unsafe { ((**self.0).GetFunctionByName)(self.0, public_name.as_ptr()) };
+
+            if function.is_null() {
+                None
+            } else {
+                Some(IPluginFunction(function))
+            }
+        }
+
+        /// Like `local_to_string`, but returns `None` if the plugin passed `NULL_STRING`.
+        pub fn local_to_string_null(&self, local: cell_t) -> Result<Option<&CStr>, SPError> {
+            unsafe {
+                let mut addr: *mut c_char = null_mut();
+                let res = ((**self.0).LocalToStringNULL)(self.0, local, &mut addr);
+
+                if res != 0 {
+                    Err(SPError(res))
+                } else if addr.is_null() {
+                    Ok(None)
+                } else {
+                    Ok(Some(CStr::from_ptr(addr)))
+                }
+            }
+        }
+
+        /// Returns whether the plugin passed `NULL_VECTOR`.
+        pub fn is_null_vector(&self, local: cell_t) -> Result<bool, SPError> {
+            let addr = self.local_to_phys_addr(local)? as *mut cell_t;
+            let null = unsafe { ((**self.0).GetNullRef)(self.0, SPNullType::VECTOR) };
+
+            Ok(!null.is_null() && addr == null)
+        }
+
+        /// Copies a string into plugin memory, truncating it to `maxbytes` (including the NUL terminator) without
+        /// splitting a UTF-8 sequence. Returns the number of bytes written, not including the terminator.
+        pub fn string_to_local_utf8(&self, local: cell_t, maxbytes: usize, source: &CStr) -> Result<usize, SPError> {
+            unsafe {
+                let mut written: usize = 0;
+                let res = ((**self.0).StringToLocalUTF8)(self.0, local, maxbytes, source.as_ptr(), &mut written);
+
+                if res == 0 {
+                    Ok(written)
+                } else {
+                    Err(SPError(res))
+                }
+            }
+        }
+
+        /// Allocates `cells` cells on the plugin's heap, which are released when the returned guard is dropped.
+        pub fn heap_alloc(&self, cells: usize) -> Result<HeapAllocation<'_>, SPError> {
+            let count = u32::try_from(cells).map_err(|_| SPError::HEAPLOW)?;
+
+            unsafe {
+                let mut local_addr = cell_t::from(0);
+                let mut phys_addr: *mut cell_t = null_mut();
+                let res = ((**self.0).HeapAlloc)(self.0, count, &mut local_addr, &mut phys_addr);
+
+                if res != 0 {
+                    return Err(SPError(res));
+                }
+
+                HEAP_ALLOCATIONS.with(|allocations| allocations.borrow_mut().push((self.0, local_addr, false)));
+
+                Ok(HeapAllocation { ctx: self, local_addr, phys_addr, cells })
+            }
+        }
+
+        /// Copies a string onto the plugin's heap, for passing to plugin code that expects a `char[]`.
+        pub fn heap_alloc_string(&self, string: &CStr) -> Result<HeapAllocation<'_>, SPError> {
+            let bytes = string.to_bytes_with_nul();
+            let cell_size = std::mem::size_of::<cell_t>();
+            let mut allocation = self.heap_alloc(bytes.len().div_ceil(cell_size))?;
+
+            unsafe {
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), allocation.as_mut_ptr() as *mut u8, bytes.len());
+            }
+
+            Ok(allocation)
+        }
+    }
+
+    thread_local! {
+        // Outstanding heap allocations, in allocation order. SourcePawn requires that they're released in reverse order,
+        // so an allocation dropped early is only marked as released until everything allocated after it is gone.
+        static HEAP_ALLOCATIONS: RefCell<Vec<(IPluginContextPtr, cell_t, bool)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// A block of memory on a plugin's heap, allocated with [`IPluginContext::heap_alloc`].
+    #[derive(Debug)]
+    pub struct HeapAllocation<'a> {
+        ctx: &'a IPluginContext,
+        local_addr: cell_t,
+        phys_addr: *mut cell_t,
+        cells: usize,
+    }
+
+    impl HeapAllocation<'_> {
+        /// The plugin-local address of the allocation, to pass to plugin code.
+        pub fn local_addr(&self) -> cell_t {
+            self.local_addr
+        }
+    }
+
+    impl Deref for HeapAllocation<'_> {
+        type Target = [cell_t];
+
+        fn deref(&self) -> &[cell_t] {
+            unsafe { std::slice::from_raw_parts(self.phys_addr, self.cells) }
+        }
+    }
+
+    impl DerefMut for HeapAllocation<'_> {
+        fn deref_mut(&mut self) -> &mut [cell_t] {
+            unsafe { std::slice::from_raw_parts_mut(self.phys_addr, self.cells) }
+        }
+    }
+
+    impl Drop for HeapAllocation<'_> {
+        fn drop(&mut self) {
+            let ctx = self.ctx.0;
+
+            HEAP_ALLOCATIONS.with(|allocations| {
+                let mut allocations = allocations.borrow_mut();
+
+                if let Some(entry) = allocations.iter_mut().rev().find(|(entry_ctx, addr, _)| *entry_ctx == ctx && *addr == self.local_addr) {
+                    entry.2 = true;
+                }
+
+                while let Some(idx) = allocations.iter().rposition(|(entry_ctx, _, _)| *entry_ctx == ctx) {
+                    let (_, addr, released) = allocations[idx];
+                    if !released {
+                        break;
+                    }
+
+                    allocations.remove(idx);
+                    unsafe {
+                        ((**ctx).HeapRelease)(ctx, addr);
+                    }
+                }
+            });
+        }
+    }
+}
+
+pub use ICallableApi::*;
+mod ICallableApi {
+    use super::types::{cell_t, ICallablePtr, ParamType, SPError};
+    use std::convert::TryFrom;
+    use std::ffi::CStr;
+    use std::marker::PhantomData;
+    use std::os::raw::{c_char, c_int};
+
+    const SM_PARAM_COPYBACK: c_int = 1 << 0;
+    const SM_PARAM_STRING_UTF8: c_int = 1 << 0;
+    const SM_PARAM_STRING_COPY: c_int = 1 << 1;
+
+    /// A call to a plugin function or forward that is being built, push the arguments then execute it.
+    ///
+    /// Arguments passed by reference are written back to when the call is executed.
+    /// The call is cancelled if it is dropped without being executed.
+    #[derive(Debug)]
+    pub struct CallBuilder<'a> {
+        callable: ICallablePtr,
+        execute: unsafe fn(ICallablePtr, *mut cell_t) -> c_int,
+        executed: bool,
+        _marker: PhantomData<&'a mut ()>,
+    }
+
+    impl<'a> CallBuilder<'a> {
+        /// # Safety
+        ///
+        /// `callable` must be valid for `'a`, and `execute` must execute the pushed call on it.
+        pub(crate) unsafe fn new(callable: ICallablePtr, execute: unsafe fn(ICallablePtr, *mut cell_t) -> c_int) -> Self {
+            CallBuilder { callable, execute, executed: false, _marker: PhantomData }
+        }
+
+        fn check(self, res: c_int) -> Result<Self, SPError> {
+            if res == 0 {
+                Ok(self)
+            } else {
+                Err(SPError(res))
+            }
+        }
+
+        /// Pushes any argument type, see [`CallArg`].
+        pub fn push<T: CallArg<'a>>(self, value: T) -> Result<Self, SPError> {
+            value.push(self)
+        }
+
+        pub fn push_cell<T: Into<cell_t>>(self, value: T) -> Result<Self, SPError> {
+            let res = unsafe { ((**self.callable).PushCell)(self.callable, value.into()) };
+            self.check(res)
+        }
+
+        pub fn push_float(self, value: f32) -> Result<Self, SPError> {
+            let res = unsafe { ((**self.callable).PushFloat)(self.callable, value) };
+            self.check(res)
+        }
+
+        pub fn push_cell_ref(self, value: &'a mut cell_t) -> Result<Self, SPError> {
+            let res = unsafe { ((**self.callable).PushCellByRef)(self.callable, value, SM_PARAM_COPYBACK) };
+            self.check(res)
+        }
+
+        pub fn push_float_ref(self, value: &'a mut f32) -> Result<Self, SPError> {
+            let res = unsafe { ((**self.callable).PushFloatByRef)(self.callable, value, SM_PARAM_COPYBACK) };
+            self.check(res)
+        }
+
+        pub fn push_array(self, values: &'a [cell_t]) -> Result<Self, SPError> {
+            let cells = u32::try_from(values.len()).map_err(|_| SPError::PARAM)?;
+
+            // Without the copy-back flag the array is only read from.
+            let res = unsafe { ((**self.callable).PushArray)(self.callable, values.as_ptr() as *mut cell_t, cells, 0) };
+            self.check(res)
+        }
+
+        pub fn push_array_mut(self, values: &'a mut [cell_t]) -> Result<Self, SPError> {
+            let cells = u32::try_from(values.len()).map_err(|_| SPError::PARAM)?;
+            let res = unsafe { ((**self.callable).PushArray)(self.callable, values.as_mut_ptr(), cells, SM_PARAM_COPYBACK) };
+            self.check(res)
+        }
+
+        pub fn push_string(self, value: &'a CStr) -> Result<Self, SPError> {
+            let res = unsafe { ((**self.callable).PushString)(self.callable, value.as_ptr()) };
+            self.check(res)
+        }
+
+        /// Pushes a `char[]` buffer that the function can write a string to, which should hold a NUL-terminated string.
+        pub fn push_string_buffer(self, buffer: &'a mut [u8]) -> Result<Self, SPError> {
+            let res = unsafe { ((**self.callable).PushStringEx)(self.callable, buffer.as_mut_ptr() as *mut c_char, buffer.len(), SM_PARAM_STRING_UTF8 | SM_PARAM_STRING_COPY, SM_PARAM_COPYBACK) };
+            self.check(res)
+        }
+
+        /// Makes the call, returning its result.
+        pub fn execute(mut self) -> Result<cell_t, SPError> {
+            self.executed = true;
+
+            let mut result = cell_t::from(0);
+            let res = unsafe { (self.execute)(self.callable, &mut result) };
+
+            if res == 0 {
+                Ok(result)
+            } else {
+                Err(SPError(res))
+            }
+        }
+    }
+
+    impl Drop for CallBuilder<'_> {
+        fn drop(&mut self) {
+            if !self.executed {
+                unsafe { ((**self.callable).Cancel)(self.callable) }
+            }
+        }
+    }
+
+    /// A value that can be pushed as an argument to a [`CallBuilder`].
+    pub trait CallArg<'a> {
+        /// The same type with any lifetime made `'static`, which is how typed forwards name their parameters.
+        type Static: 'static;
+
+        fn param_type() -> ParamType;
+
+        fn push(self, call: CallBuilder<'a>) -> Result<CallBuilder<'a>, SPError>;
+    }
+
+    macro_rules! impl_call_arg {
+        ($($ty:ty: $static:ty, $param:ident => $method:ident),* $(,)?) => {
+            $(
+                impl<'a> CallArg<'a> for $ty {
+                    type Static = $static;
+
+                    fn param_type() -> ParamType {
+                        ParamType::$param
+                    }
+
+                    fn push(self, call: CallBuilder<'a>) -> Result<CallBuilder<'a>, SPError> {
+                        call.$method(self)
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_call_arg!(
+        cell_t: cell_t, CELL => push_cell,
+        i32: i32, CELL => push_cell,
+        bool: bool, CELL => push_cell,
+        f32: f32, FLOAT => push_float,
+        &'a mut cell_t: &'static mut cell_t, CELL_BY_REF => push_cell_ref,
+        &'a mut f32: &'static mut f32, FLOAT_BY_REF => push_float_ref,
+        &'a [cell_t]: &'static [cell_t], ARRAY => push_array,
+        &'a mut [cell_t]: &'static mut [cell_t], ARRAY => push_array_mut,
+        &'a CStr: &'static CStr, STRING => push_string,
+    );
+
+    /// A tuple of [`CallArg`] values, to push all of the arguments of a call at once.
+    pub trait CallArgs<'a> {
+        type Static: 'static;
+
+        fn param_types() -> Vec<ParamType>;
+
+        fn push_all(self, call: CallBuilder<'a>) -> Result<CallBuilder<'a>, SPError>;
+    }
+
+    macro_rules! impl_call_args {
+        ($($name:ident),*) => {
+            impl<'a, $($name: CallArg<'a>),*> CallArgs<'a> for ($($name,)*) {
+                type Static = ($($name::Static,)*);
+
+                fn param_types() -> Vec<ParamType> {
+                    vec![$($name::param_type()),*]
+                }
+
+                #[allow(non_snake_case)]
+                fn push_all(self, call: CallBuilder<'a>) -> Result<CallBuilder<'a>, SPError> {
+                    let ($($name,)*) = self;
+                    $(let call = call.push($name)?;)*
+                    Ok(call)
+                }
+            }
+        };
+    }
+
+    impl_call_args!();
+    impl_call_args!(A);
+    impl_call_args!(A, B);
+    impl_call_args!(A, B, C);
+    impl_call_args!(A, B, C, D);
+    impl_call_args!(A, B, C, D, E);
+    impl_call_args!(A, B, C, D, E, F);
+    impl_call_args!(A, B, C, D, E, F, G);
+    impl_call_args!(A, B, C, D, E, F, G, H);
+}
+
+pub use IPluginFunctionApi::*;
+mod IPluginFunctionApi {
+    pub use super::vtables::IPluginFunction;
+
+    use super::types::{cell_t, ICallablePtr, IPluginContextPtr, IPluginFunctionPtr, IPluginPtr, SPError};
+    use super::{CallArgs, CallBuilder, IPluginContext};
+    use std::cell::{Cell, RefCell};
+    use std::os::raw::c_int;
+    use std::rc::{Rc, Weak};
+
+    impl IPluginFunction {
+        pub fn get_parent_context(&self) -> IPluginContext {
+            unsafe { IPluginContext(((**self.0).GetParentContext)(self.0)) }
+        }
+
+        /// Starts a call to the function, see [`CallBuilder`].
+        pub fn prepare_call(&self) -> CallBuilder<'_> {
+            unsafe fn execute(callable: ICallablePtr, result: *mut cell_t) -> c_int {
+                let function = callable as IPluginFunctionPtr;
+                ((**function).Execute)(function, result)
+            }
+
+            unsafe { CallBuilder::new(self.0 as ICallablePtr, execute) }
+        }
+
+        /// Calls the function with a tuple of arguments, returning its result.
+        pub fn call<'a, A: CallArgs<'a>>(&'a self, args: A) -> Result<cell_t, SPError> {
+            args.push_all(self.prepare_call())?.execute()
+        }
+    }
+
+    thread_local! {
+        // The context that owns each stored function, and whether its plugin is still loaded.
+        static STORED_FUNCTIONS: RefCell<Vec<(IPluginContextPtr, Weak<Cell<bool>>)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// A plugin function that can be kept and called after the native that received it has returned.
+    ///
+    /// An [`IPluginFunction`] dangles once its plugin is unloaded, this is invalidated instead and calls to it fail.
+    /// Needs [`IPluginManager::listen_for_unloaded_plugins`](super::IPluginManager::listen_for_unloaded_plugins) to have been called.
+    #[derive(Debug)]
+    pub struct PluginFunction {
+        function: IPluginFunction,
+        context: IPluginContextPtr,
+        loaded: Rc<Cell<bool>>,
+    }
+
+    impl PluginFunction {
+        pub fn new(function: IPluginFunction) -> Result<PluginFunction, &'static str> {
+            if !super::IPluginManagerApi::is_listening_for_unloaded_plugins() {
+                return Err("Plugin functions can't be stored without listening for unloaded plugins");
+            }
+
+            let context = function.get_parent_context().0;
+            let loaded = Rc::new(Cell::new(true));
+            STORED_FUNCTIONS.with(|functions| functions.borrow_mut().push((context, Rc::downgrade(&loaded))));
+
+            Ok(PluginFunction { function, context, loaded })
+        }
+
+        // The context of the plugin that owns the function, which stays valid to compare against after it's unloaded.
+        pub(crate) fn context(&self) -> IPluginContextPtr {
+            self.context
+        }
+
+        /// Returns the function if its plugin is still loaded and it can be called.
+        pub fn get(&self) -> Option<&IPluginFunction> {
+            if self.loaded.get() && self.function.is_runnable() {
+                Some(&self.function)
+            } else {
+                None
+            }
+        }
+
+        pub fn is_runnable(&self) -> bool {
+            self.get().is_some()
+        }
+
+        pub fn prepare_call(&self) -> Result<CallBuilder<'_>, SPError> {
+            self.get().map(IPluginFunction::prepare_call).ok_or(SPError::NOT_RUNNABLE)
+        }
+
+        pub fn call<'a, A: CallArgs<'a>>(&'a self, args: A) -> Result<cell_t, SPError> {
+            self.get().ok_or(SPError::NOT_RUNNABLE)?.call(args)
+        }
+    }
+
+    impl Drop for PluginFunction {
+        fn drop(&mut self) {
+            STORED_FUNCTIONS.with(|functions| functions.borrow_mut().retain(|(_, loaded)| loaded.strong_count() > 0 && !std::ptr::eq(loaded.as_ptr(), Rc::as_ptr(&self.loaded))));
+        }
+    }
+
+    pub(crate) fn invalidate_functions_of_plugin(plugin: IPluginPtr) {
+        let context = unsafe { ((**plugin).GetBaseContext)(plugin) };
+
+        STORED_FUNCTIONS.with(|functions| {
+            functions.borrow_mut().retain(|(owner, loaded)| match loaded.upgrade() {
+                Some(loaded) if *owner == context => {
+                    loaded.set(false);
+                    false
+                }
+                Some(_) => true,
+                None => false,
+            })
+        });
+    }
+
+    // Once we stop listening for unloaded plugins, no stored function can be trusted.
+    pub(crate) fn invalidate_all_functions() {
+        let functions = STORED_FUNCTIONS.with(|functions| functions.replace(Vec::new()));
+        for (_, loaded) in functions {
+            if let Some(loaded) = loaded.upgrade() {
+                loaded.set(false);
+            }
+        }
+    }
+}
+
+pub use IPluginManagerApi::*;
+mod IPluginManagerApi {
+    pub use super::vtables::IPluginManager;
+
+    use super::types::{IPluginManagerPtr, IPluginPtr, IPluginsListenerPtr};
+    use super::vtables::IPluginsListenerVtable;
+    use super::{RequestableInterface, SMInterface};
+    use sm_ext_derive::vtable_adapter;
+    use std::cell::RefCell;
+    use std::os::raw::c_uint;
+
+    impl RequestableInterface for IPluginManager {
+        fn get_interface_name() -> &'static str {
+            "IPluginManager"
+        }
+
+        fn get_interface_version() -> u32 {
+            5
+        }
+
+        unsafe fn from_raw_interface(iface: SMInterface) -> Self {
+            IPluginManager(iface.0 as IPluginManagerPtr)
+        }
+    }
+
+    thread_local! {
+        // Our listener, added to the plugin manager the first time something needs to know about unloaded plugins.
+        static PLUGINS_LISTENER: RefCell<Option<(IPluginManagerPtr, Box<PluginsListener>)>> = const { RefCell::new(None) };
+    }
+
+    #[repr(C)]
+    struct PluginsListener {
+        vtable: *mut IPluginsListenerVtable,
+    }
+
+    impl Drop for PluginsListener {
+        fn drop(&mut self) {
+            unsafe {
+                drop(Box::from_raw(self.vtable));
+            }
+        }
+    }
+
+    #[vtable_adapter(IPluginsListenerVtable)]
+    impl PluginsListener {
+        fn on_plugin_created(&mut self, plugin: IPluginPtr) {}
+
+        fn on_plugin_loaded(&mut self, plugin: IPluginPtr) {}
+
+        fn on_plugin_pause_change(&mut self, plugin: IPluginPtr, paused: bool) {}
+
+        fn on_plugin_unloaded(&mut self, plugin: IPluginPtr) {
+            super::IForwardManagerApi::remove_functions_of_plugin(plugin);
+            super::IPluginFunctionApi::invalidate_functions_of_plugin(plugin);
+            super::executor::cancel_tasks_of_plugin(plugin);
+        }
+
+        fn on_plugin_destroyed(&mut self, plugin: IPluginPtr) {}
+
+        fn on_plugin_will_unload(&mut self, plugin: IPluginPtr) {}
+
+        fn get_api_version(&mut self) -> c_uint {
+            7
+        }
+    }
+
+    impl IPluginManager {
+        /// Starts tracking unloaded plugins so that stored [`PluginFunction`](super::PluginFunction)s are invalidated, until the extension is unloaded.
+        pub fn listen_for_unloaded_plugins(&self) {
+            listen_for_unloaded_plugins(self);
+        }
+    }
+
+    pub(crate) fn is_listening_for_unloaded_plugins() -> bool {
+        PLUGINS_LISTENER.with(|listener| listener.borrow().is_some())
+    }
+
+    pub(crate) fn listen_for_unloaded_plugins(plugins: &IPluginManager) {
+        PLUGINS_LISTENER.with(|listener| {
+            let mut listener = listener.borrow_mut();
+            if listener.is_some() {
+                return;
+            }
+
+            let mut adapter = Box::new(PluginsListener { vtable: Box::into_raw(Box::new(PluginsListener::vtable())) });
+            unsafe {
+                ((**plugins.0).AddPluginsListener)(plugins.0, &mut *adapter as *mut PluginsListener as IPluginsListenerPtr);
+            }
+
+            *listener = Some((plugins.0, adapter));
+        });
+    }
+
+    pub(crate) fn remove_plugins_listener() {
+        if let Some((plugins, mut adapter)) = PLUGINS_LISTENER.with(|listener| listener.borrow_mut().take()) {
+            unsafe {
+                ((**plugins).RemovePluginsListener)(plugins, &mut *adapter as *mut PluginsListener as IPluginsListenerPtr);
+            }
+        }
+
+        super::IPluginFunctionApi::invalidate_all_functions();
+    }
+}
+
+pub use IForwardManagerApi::*;
+mod IForwardManagerApi {
+    pub use super::vtables::{IChangeableForward, IForward, IForwardManager};
+
+    use super::types::{cell_t, ExecType, ICallablePtr, IChangeableForwardPtr, IForwardManagerPtr, IForwardPtr, IPluginPtr, SPError};
+    use super::{CallArgs, CallBuilder, IPluginFunction, IPluginManager, RequestableInterface, SMInterface};
+    use std::cell::RefCell;
+    use std::convert::TryFrom;
+    use std::ffi::CStr;
+    use std::marker::PhantomData;
+    use std::os::raw::c_int;
+    use std::ptr::{null, null_mut};
+
+    thread_local! {
+        // Forwards that haven't been released yet, so that they can all be released when the extension is unloaded.
+        // Private forwards are also kept here so that the functions of unloaded plugins can be removed from them.
+        static FORWARDS: RefCell<Vec<(IForwardManagerPtr, IForwardPtr, bool)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    unsafe fn execute_forward(callable: ICallablePtr, result: *mut cell_t) -> c_int {
+        let forward = callable as IForwardPtr;
+        ((**forward).Execute)(forward, result, null_mut())
+    }
+
+    impl RequestableInterface for IForwardManager {
+        fn get_interface_name() -> &'static str {
+            "IForwardManager"
+        }
+
+        fn get_interface_version() -> u32 {
+            4
+        }
+
+        unsafe fn from_raw_interface(iface: SMInterface) -> Self {
+            IForwardManager(iface.0 as IForwardManagerPtr)
+        }
+    }
+
+    impl IForwardManager {
+        /// Creates a forward that calls the public function called `name` in every plugin.
+        ///
+        /// The parameter types are those of `A`, a tuple of [`CallArg`](super::CallArg) types such as `(i32, &'static CStr)`.
+        pub fn create_global_forward<A: CallArgs<'static, Static = A>>(&self, name: &CStr, exec_type: ExecType) -> Result<GlobalForward<A>, &'static str> {
+            let params = A::param_types();
+            let num_params = u32::try_from(params.len()).map_err(|_| "Too many forward parameters")?;
+            let forward = unsafe { ((**self.0).CreateForward)(self.0, name.as_ptr(), exec_type, num_params, params.as_ptr()) };
+
+            if forward.is_null() {
+                return Err("Failed to create forward");
+            }
+
+            FORWARDS.with(|forwards| forwards.borrow_mut().push((self.0, forward, false)));
+
+            Ok(GlobalForward { manager: self.0, forward: IForward(forward), _marker: PhantomData })
+        }
+
+        /// Creates a forward that calls the plugin functions added to it, with the parameter types of `A`.
+        ///
+        /// The plugin manager is used to remove the functions of plugins as they are unloaded.
+        pub fn create_private_forward<A: CallArgs<'static, Static = A>>(&self, plugins: &IPluginManager, exec_type: ExecType) -> Result<PrivateForward<A>, &'static str> {
+            let params = A::param_types();
+            let num_params = i32::try_from(params.len()).map_err(|_| "Too many forward parameters")?;
+            let forward = unsafe { ((**self.0).CreateForwardEx)(self.0, null(), exec_type, num_params, params.as_ptr()) };
+
+            if forward.is_null() {
+                return Err("Failed to create forward");
+            }
+
+            super::IPluginManagerApi::listen_for_unloaded_plugins(plugins);
+            FORWARDS.with(|forwards| forwards.borrow_mut().push((self.0, forward as IForwardPtr, true)));
+
+            Ok(PrivateForward { manager: self.0, forward: IChangeableForward(forward), _marker: PhantomData })
+        }
+    }
+
+    /// A forward created with [`IForwardManager::create_global_forward`], which calls a public function in every plugin.
+    ///
+    /// The forward is released when this is dropped, or when the extension is unloaded.
+    #[derive(Debug)]
+    pub struct GlobalForward<A> {
+        manager: IForwardManagerPtr,
+        forward: IForward,
+        _marker: PhantomData<fn(A)>,
+    }
+
+    impl<A> GlobalForward<A> {
+        pub fn forward(&self) -> &IForward {
+            &self.forward
+        }
+
+        /// Starts a call to the forward, see [`CallBuilder`]. The result is combined according to the forward's [`ExecType`].
+        ///
+        /// The pushed arguments aren't checked against the forward's parameters, prefer `fire`.
+        pub fn prepare_call(&self) -> CallBuilder<'_> {
+            unsafe { CallBuilder::new(self.forward.0 as ICallablePtr, execute_forward) }
+        }
+
+        /// Calls the forward with a tuple of arguments, returning the combined result.
+        pub fn fire<'a, T: CallArgs<'a, Static = A>>(&'a self, args: T) -> Result<cell_t, SPError> {
+            args.push_all(self.prepare_call())?.execute()
+        }
+    }
+
+    impl<A> Drop for GlobalForward<A> {
+        fn drop(&mut self) {
+            release_forward(self.manager, self.forward.0);
+        }
+    }
+
+    /// A forward created with [`IForwardManager::create_private_forward`], which calls the plugin functions added to it.
+    ///
+    /// Functions are removed automatically when their plugin is unloaded.
+    /// The forward is released when this is dropped, or when the extension is unloaded.
+    #[derive(Debug)]
+    pub struct PrivateForward<A> {
+        manager: IForwardManagerPtr,
+        forward: IChangeableForward,
+        _marker: PhantomData<fn(A)>,
+    }
+
+    impl<A> PrivateForward<A> {
+        pub fn forward(&self) -> &IChangeableForward {
+            &self.forward
+        }
+
+        /// Adds a function to the forward, returning false if it couldn't be added, such as if it already was.
+        pub fn add_function(&self, function: &IPluginFunction) -> bool {
+            unsafe { ((**self.forward.0).AddFunction)(self.forward.0, function.0) }
+        }
+
+        /// Removes a function from the forward, returning false if it wasn't added.
+        pub fn remove_function(&self, function: &IPluginFunction) -> bool {
+            unsafe { ((**self.forward.0).RemoveFunction)(self.forward.0, function.0) }
+        }
+
+        /// Starts a call to the forward, see [`CallBuilder`]. The result is combined according to the forward's [`ExecType`].
+        ///
+        /// The pushed arguments aren't checked against the forward's parameters, prefer `fire`.
+        pub fn prepare_call(&self) -> CallBuilder<'_> {
+            unsafe { CallBuilder::new(self.forward.0 as ICallablePtr, execute_forward) }
+        }
+
+        /// Calls the forward with a tuple of arguments, returning the combined result.
+        pub fn fire<'a, T: CallArgs<'a, Static = A>>(&'a self, args: T) -> Result<cell_t, SPError> {
+            args.push_all(self.prepare_call())?.execute()
+        }
+    }
+
+    impl<A> Drop for PrivateForward<A> {
+        fn drop(&mut self) {
+            release_forward(self.manager, self.forward.0 as IForwardPtr);
+        }
+    }
+
+    pub(crate) fn remove_functions_of_plugin(plugin: IPluginPtr) {
+        let forwards = FORWARDS.with(|forwards| forwards.borrow().clone());
+
+        for (_, forward, changeable) in forwards {
+            if changeable {
+                let forward = forward as IChangeableForwardPtr;
+                unsafe {
+                    ((**forward).RemoveFunctionsOfPlugin)(forward, plugin);
+                }
+            }
+        }
+    }
+
+    fn release_forward(manager: IForwardManagerPtr, forward: IForwardPtr) {
+        let registered = FORWARDS.with(|forwards| {
+            let mut forwards = forwards.borrow_mut();
+            match forwards.iter().position(|&(_, entry, _)| entry == forward) {
+                Some(idx) => {
+                    forwards.remove(idx);
+                    true
+                }
+                None => false,
+            }
+        });
+
+        if registered {
+            unsafe { ((**manager).ReleaseForward)(manager, forward) }
+        }
+    }
+
+    pub(crate) fn release_all_forwards() {
+        let forwards = FORWARDS.with(|forwards| forwards.borrow_mut().split_off(0));
+
+        for (manager, forward, _) in forwards.into_iter().rev() {
+            unsafe { ((**manager).ReleaseForward)(manager, forward) }
+        }
+
+        super::IPluginManagerApi::remove_plugins_listener();
+    }
+}
+
+pub use IHandleSysApi::*;
+mod IHandleSysApi {
+    pub use super::vtables::IHandleSys;
+
+    use super::types::{cell_t, HandleAccess, HandleError, HandleSecurity, HandleType_t, Handle_t, IHandleSysPtr, IHandleTypeDispatchPtr, IdentityTokenPtr, TryFromWithContext, TypeAccess};
+    use super::vtables::IHandleTypeDispatchVtable;
+    use super::{IPluginContext, RequestableInterface, SMInterface};
+    use sm_ext_derive::vtable_adapter;
+    use std::any::TypeId;
+    use std::cell::RefCell;
+    use std::convert::TryFrom;
+    use std::ffi::CStr;
+    use std::ops::{Deref, DerefMut};
+    use std::os::raw::{c_uint, c_void};
+    use std::ptr::{null, null_mut};
+
+    impl RequestableInterface for IHandleSys {
+        fn get_interface_name() -> &'static str {
+            "IHandleSys"
+        }
+
+        fn get_interface_version() -> u32 {
+            5
+        }
+
+        unsafe fn from_raw_interface(iface: SMInterface) -> Self {
+            IHandleSys(iface.0 as IHandleSysPtr)
+        }
+    }
+
+    thread_local! {
+        // Handle types that haven't been removed yet, so that they can all be removed when the extension is unloaded.
+        // The Rust type of each is kept so that natives can find the handle type to read their parameters as.
+        static HANDLE_TYPES: RefCell<Vec<(IHandleSysPtr, HandleType_t, IdentityTokenPtr, TypeId)>> = const { RefCell::new(Vec::new()) };
+
+        // Objects currently borrowed by a HandleRef or HandleMut, and whether the borrow is exclusive.
+        static BORROWED_OBJECTS: RefCell<Vec<(*mut c_void, bool)>> = const { RefCell::new(Vec::new()) };
+
+        // Objects whose handles were destroyed while they were borrowed, dropped when the last borrow ends.
+        static DESTROYED_OBJECTS: RefCell<Vec<(*mut c_void, DropObjectFn)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    impl IHandleSys {
+        /// Creates a handle type whose handles each own a `T`, which is dropped when the handle is closed.
+        ///
+        /// `ident` owns the type, this is usually the extension's identity from [`IExtension::get_identity`](super::IExtension::get_identity).
+        /// Natives find the handle type by `T`, so only one handle type can exist for each `T` at a time, otherwise this fails with [`HandleError::PARAMETER`].
+        pub fn create_type<T: 'static>(&self, name: &CStr, ident: IdentityTokenPtr) -> Result<HandleType<T>, HandleError> {
+            self.create_type_ex(name, ident, null())
+        }
+
+        /// Like [`IHandleSys::create_type`], but with default permissions for the type's handles, such as to forbid plugins from cloning them.
+        ///
+        /// Start from [`IHandleSys::init_access_defaults`] to only change some of the permissions.
+        pub fn create_type_with_access<T: 'static>(&self, name: &CStr, ident: IdentityTokenPtr, handle_access: &HandleAccess) -> Result<HandleType<T>, HandleError> {
+            self.create_type_ex(name, ident, handle_access)
+        }
+
+        /// Returns the permissions used for handle types and handles that don't specify their own.
+        pub fn init_access_defaults(&self) -> (TypeAccess, HandleAccess) {
+            let mut type_access = TypeAccess { hsVersion: 0, ident: null_mut(), access: [false; 2] };
+            let mut handle_access = HandleAccess { hsVersion: 0, access: [0; 3] };
+
+            unsafe {
+                ((**self.0).InitAccessDefaults)(self.0, &mut type_access, &mut handle_access);
+            }
+
+            (type_access, handle_access)
+        }
+
+        fn create_type_ex<T: 'static>(&self, name: &CStr, ident: IdentityTokenPtr, handle_access: *const HandleAccess) -> Result<HandleType<T>, HandleError> {
+            if HANDLE_TYPES.with(|types| types.borrow().iter().any(|&(_, _, _, type_id)| type_id == TypeId::of::<T>())) {
+                return Err(HandleError::PARAMETER);
+            }
+
+            let mut dispatch = Box::new(HandleTypeDispatch::<T> { vtable: Box::into_raw(Box::new(HandleTypeDispatch::<T>::vtable())), approx_size: None });
+
+            let mut err = HandleError::NONE;
+            let id = unsafe { ((**self.0).CreateType)(self.0, name.as_ptr(), &mut *dispatch as *mut HandleTypeDispatch<T> as IHandleTypeDispatchPtr, 0, null(), handle_access, ident, &mut err) };
+
+            if id == 0 {
+                return Err(err);
+            }
+
+            HANDLE_TYPES.with(|types| types.borrow_mut().push((self.0, id, ident, TypeId::of::<T>())));
+
+            Ok(HandleType { sys: self.0, id, ident, dispatch })
+        }
+    }
+
+    #[repr(C)]
+    struct HandleTypeDispatch<T> {
+        vtable: *mut IHandleTypeDispatchVtable,
+        approx_size: Option<fn(&T) -> usize>,
+    }
+
+    impl<T> Drop for HandleTypeDispatch<T> {
+        fn drop(&mut self) {
+            unsafe {
+                drop(Box::from_raw(self.vtable));
+            }
+        }
+    }
+
+    #[vtable_adapter(IHandleTypeDispatchVtable)]
+    impl<T> HandleTypeDispatch<T> {
+        fn get_dispatch_version(&mut self) -> c_uint {
+            5
+        }
+
+        fn on_handle_destroy(&mut self, handle_type: HandleType_t, object: *mut c_void) {
+            // A plugin can close a handle while a native it called into is still using the object.
+            if BORROWED_OBJECTS.with(|borrowed| borrowed.borrow().iter().any(|&(entry, _)| entry == object)) {
+                DESTROYED_OBJECTS.with(|destroyed| destroyed.borrow_mut().push((object, drop_object::<T>)));
+                return;
+            }
+
+            unsafe { drop_object::<T>(object) }
+        }
+
+        fn get_handle_approx_size(&mut self, handle_type: HandleType_t, object: *mut c_void, size: *mut c_uint) -> bool {
+            let approx_size = match self.approx_size {
+                // The object can't be looked at while a native has it borrowed mutably.
+                Some(approx_size) if !BORROWED_OBJECTS.with(|borrowed| borrowed.borrow().contains(&(object, true))) => approx_size(unsafe { &*(object as *const T) }),
+                _ => std::mem::size_of::<T>(),
+            };
+
+            unsafe {
+                *size = c_uint::try_from(approx_size).unwrap_or(c_uint::MAX);
+            }
+
+            true
+        }
+    }
+
+    type DropObjectFn = unsafe fn(*mut c_void);
+
+    unsafe fn drop_object<T>(object: *mut c_void) {
+        drop(Box::from_raw(object as *mut T));
+    }
+
+    /// A handle type created with [`IHandleSys::create_type`].
+    ///
+    /// The type is removed when this is dropped, or when the extension is unloaded, which closes all of its handles.
+    pub struct HandleType<T> {
+        sys: IHandleSysPtr,
+        id: HandleType_t,
+        ident: IdentityTokenPtr,
+        // Must outlive the type, as SourceMod calls into it whenever one of the handles is destroyed.
+        dispatch: Box<HandleTypeDispatch<T>>,
+    }
+
+    impl<T> HandleType<T> {
+        pub fn id(&self) -> HandleType_t {
+            self.id
+        }
+
+        /// Sets the function used to report the memory used by each object, such as in `sm_dump_handles`.
+        ///
+        /// Without one, only the size of `T` itself is reported.
+        pub fn set_approx_size(&mut self, approx_size: fn(&T) -> usize) {
+            self.dispatch.approx_size = Some(approx_size);
+        }
+
+        /// Creates a handle that takes ownership of `value`, to be returned from a native.
+        ///
+        /// `owner` is usually the identity of the calling plugin, from [`IPluginContext::get_identity`](super::IPluginContext::get_identity).
+        // Identity tokens are opaque to us, they're only passed through to SourceMod.
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub fn create_handle(&self, value: T, owner: IdentityTokenPtr) -> Result<cell_t, HandleError> {
+            let object = Box::into_raw(Box::new(value));
+
+            let mut err = HandleError::NONE;
+            let handle = unsafe { ((**self.sys).CreateHandle)(self.sys, self.id, object as *mut c_void, owner, self.ident, &mut err) };
+
+            if handle == 0 {
+                unsafe { drop(Box::from_raw(object)) };
+                return Err(err);
+            }
+
+            // Handles use all 32 bits, plugins see them as the same bits in a cell.
+            Ok((handle as i32).into())
+        }
+
+        /// Creates another handle to the same object, owned by `new_owner`. The object is dropped once all of its handles are closed.
+        ///
+        /// `owner` is checked against the handle's clone permissions, it is usually the identity of the calling plugin.
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub fn clone_handle(&self, handle: Handle_t, owner: IdentityTokenPtr, new_owner: IdentityTokenPtr) -> Result<cell_t, HandleError> {
+            let security = HandleSecurity { pOwner: owner, pIdentity: self.ident };
+            let mut new_handle = 0;
+            let err = unsafe { ((**self.sys).CloneHandle)(self.sys, handle, &mut new_handle, new_owner, &security) };
+
+            if err != HandleError::NONE {
+                return Err(err);
+            }
+
+            Ok((new_handle as i32).into())
+        }
+
+        /// Closes a handle, dropping the object if it was the last handle to it.
+        ///
+        /// `owner` is checked against the handle's delete permissions, it is usually the identity of the calling plugin.
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub fn free_handle(&self, handle: Handle_t, owner: IdentityTokenPtr) -> Result<(), HandleError> {
+            let security = HandleSecurity { pOwner: owner, pIdentity: self.ident };
+            let err = unsafe { ((**self.sys).FreeHandle)(self.sys, handle, &security) };
+
+            if err != HandleError::NONE {
+                return Err(err);
+            }
+
+            Ok(())
+        }
+    }
+
+    impl<T> Drop for HandleType<T> {
+        fn drop(&mut self) {
+            remove_type(self.sys, self.id, self.ident);
+        }
+    }
+
+    fn remove_type(sys: IHandleSysPtr, id: HandleType_t, ident: IdentityTokenPtr) {
+        let registered = HANDLE_TYPES.with(|types| {
+            let mut types = types.borrow_mut();
+            match types.iter().position(|&(_, entry, _, _)| entry == id) {
+                Some(idx) => {
+                    types.remove(idx);
+                    true
+                }
+                None => false,
+            }
+        });
+
+        if registered {
+            unsafe {
+                ((**sys).RemoveType)(sys, id, ident);
+            }
+        }
+    }
+
+    /// A native parameter that reads a handle created by a [`HandleType<T>`], borrowing its object.
+    ///
+    /// The handle is read with the calling plugin as the owner and the handle type's identity, so its access rights apply.
+    pub struct HandleRef<'a, T> {
+        handle: Handle_t,
+        object: &'a T,
+    }
+
+    impl<T> HandleRef<'_, T> {
+        pub fn handle(&self) -> Handle_t {
+            self.handle
+        }
+    }
+
+    impl<T> Deref for HandleRef<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            self.object
+        }
+    }
+
+    impl<T> Drop for HandleRef<'_, T> {
+        fn drop(&mut self) {
+            release_object(self.object as *const T as *mut c_void, false);
+        }
+    }
+
+    impl<'a, T: 'static> TryFromWithContext<'a, cell_t> for HandleRef<'a, T> {
+        type Error = String;
+
+        fn try_from_plugin(ctx: &'a IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
+            let (handle, object) = read_handle::<T>(ctx, value)?;
+            borrow_object(object as *mut c_void, false)?;
+
+            Ok(HandleRef { handle, object: unsafe { &*object } })
+        }
+    }
+
+    /// Like [`HandleRef`], but borrows the object mutably. The handle can't be used by any other parameter at the same time.
+    pub struct HandleMut<'a, T> {
+        handle: Handle_t,
+        object: &'a mut T,
+    }
+
+    impl<T> HandleMut<'_, T> {
+        pub fn handle(&self) -> Handle_t {
+            self.handle
+        }
+    }
+
+    impl<T> Deref for HandleMut<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            self.object
+        }
+    }
+
+    impl<T> DerefMut for HandleMut<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            self.object
+        }
+    }
+
+    impl<T> Drop for HandleMut<'_, T> {
+        fn drop(&mut self) {
+            release_object(self.object as *mut T as *mut c_void, true);
+        }
+    }
+
+    impl<'a, T: 'static> TryFromWithContext<'a, cell_t> for HandleMut<'a, T> {
+        type Error = String;
+
+        fn try_from_plugin(ctx: &'a IPluginContext, value: cell_t) -> Result<Self, Self::Error> {
+            let (handle, object) = read_handle::<T>(ctx, value)?;
+            borrow_object(object as *mut c_void, true)?;
+
+            Ok(HandleMut { handle, object: unsafe { &mut *object } })
+        }
+    }
+
+    fn read_handle<T: 'static>(ctx: &IPluginContext, value: cell_t) -> Result<(Handle_t, *mut T), String> {
+        let handle = i32::from(value) as Handle_t;
+
+        let handle_type = HANDLE_TYPES.with(|types| types.borrow().iter().find(|&&(_, _, _, type_id)| type_id == TypeId::of::<T>()).copied());
+        let (sys, id, ident, _) = handle_type.ok_or_else(|| format!("No handle type has been created for {}", std::any::type_name::<T>()))?;
+
+        let security = HandleSecurity { pOwner: ctx.get_identity(), pIdentity: ident };
+        let mut object = null_mut();
+        let err = unsafe { ((**sys).ReadHandle)(sys, handle, id, &security, &mut object) };
+
+        if err != HandleError::NONE {
+            return Err(format!("Invalid handle {:x} ({})", handle, err));
+        }
+
+        Ok((handle, object as *mut T))
+    }
+
+    fn borrow_object(object: *mut c_void, exclusive: bool) -> Result<(), String> {
+        BORROWED_OBJECTS.with(|borrowed| {
+            let mut borrowed = borrowed.borrow_mut();
+            if borrowed.iter().any(|&(entry, entry_exclusive)| entry == object && (exclusive || entry_exclusive)) {
+                return Err("Handle is already in use".to_string());
+            }
+
+            borrowed.push((object, exclusive));
+            Ok(())
+        })
+    }
+
+    fn release_object(object: *mut c_void, exclusive: bool) {
+        let still_borrowed = BORROWED_OBJECTS.with(|borrowed| {
+            let mut borrowed = borrowed.borrow_mut();
+            if let Some(idx) = borrowed.iter().rposition(|&entry| entry == (object, exclusive)) {
+                borrowed.remove(idx);
+            }
+
+            borrowed.iter().any(|&(entry, _)| entry == object)
+        });
+
+        if still_borrowed {
+            return;
+        }
+
+        let destroyed = DESTROYED_OBJECTS.with(|destroyed| {
+            let mut destroyed = destroyed.borrow_mut();
+            destroyed.iter().position(|&(entry, _)| entry == object).map(|idx| destroyed.remove(idx))
+        });
+
+        if let Some((object, drop_object)) = destroyed {
+            unsafe { drop_object(object) }
+        }
+    }
+
+    pub(crate) fn remove_all_handle_types() {
+        let types = HANDLE_TYPES.with(|types| types.borrow_mut().split_off(0));
+
+        for (sys, id, ident, _) in types.into_iter().rev() {
+            unsafe {
+                ((**sys).RemoveType)(sys, id, ident);
+            }
+        }
+    }
 }
 
-pub use IExtensionInterfaceApi::*;
-mod IExtensionInterfaceApi {
-    use super::types::{IExtensionInterfacePtr, IExtensionPtr, IShareSysPtr, SMInterfacePtr};
-    use super::vtables::IExtensionInterfaceVtable;
-    use super::{IExtension, IShareSys, SMInterface};
+pub use ISourceModApi::*;
+mod ISourceModApi {
+    pub use super::vtables::ISourceMod;
 
-    use libc::size_t;
+    use super::types::{ISourceModPtr, PathType};
+    use super::{IExtension, RequestableInterface, SMInterface};
+    use c_str_macro::c_str;
+    use std::cell::RefCell;
     use std::ffi::{CStr, CString};
-    use std::os::raw::{c_char, c_int, c_void};
+    use std::os::raw::c_char;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::{Arc, Mutex};
+
+    impl RequestableInterface for ISourceMod {
+        fn get_interface_name() -> &'static str {
+            "ISourceMod"
+        }
+
+        fn get_interface_version() -> u32 {
+            14
+        }
+
+        unsafe fn from_raw_interface(iface: SMInterface) -> Self {
+            ISourceMod(iface.0 as ISourceModPtr)
+        }
+    }
+
+    type Task = Box<dyn FnOnce() + Send>;
+
+    // Tasks waiting for the next game frame, or None once the extension has been unloaded.
+    type TaskQueue = Arc<Mutex<Option<Vec<Task>>>>;
+
+    thread_local! {
+        // The queue drained by our game frame hook, added the first time a dispatcher is requested.
+        static DISPATCH_QUEUE: RefCell<Option<(ISourceModPtr, TaskQueue)>> = const { RefCell::new(None) };
+    }
+
+    // Strings from Rust can contain NULs, which would otherwise cut them short.
+    pub(crate) fn to_c_string(message: &str) -> CString {
+        CString::new(message.replace('\0', "")).unwrap_or_default()
+    }
+
+    impl ISourceMod {
+        /// Returns the absolute path to a file or folder, such as `logs` relative to [`PathType::SM`].
+        pub fn build_path(&self, path_type: PathType, path: &str) -> String {
+            let path = to_c_string(path);
+            let mut buffer = [0 as c_char; 4096];
+
+            unsafe {
+                ((**self.0).BuildPath)(self.0, path_type, buffer.as_mut_ptr(), buffer.len(), c_str!("%s").as_ptr(), path.as_ptr());
+
+                CStr::from_ptr(buffer.as_ptr()).to_string_lossy().into_owned()
+            }
+        }
+
+        /// Writes a message to the SourceMod log, tagged with the extension's tag.
+        pub fn log_message(&self, myself: &IExtension, message: &str) {
+            let message = to_c_string(message);
+
+            unsafe { ((**self.0).LogMessage)(self.0, myself.0, c_str!("%s").as_ptr(), message.as_ptr()) }
+        }
+
+        /// Writes a message to the SourceMod error log, tagged with the extension's tag.
+        pub fn log_error(&self, myself: &IExtension, message: &str) {
+            let message = to_c_string(message);
+
+            unsafe { ((**self.0).LogError)(self.0, myself.0, c_str!("%s").as_ptr(), message.as_ptr()) }
+        }
+
+        /// Returns a [`Dispatcher`] that other threads can use to run closures on the main thread.
+        pub fn dispatcher(&self) -> Dispatcher {
+            DISPATCH_QUEUE.with(|queue| {
+                let mut queue = queue.borrow_mut();
+                let (_, tasks) = queue.get_or_insert_with(|| {
+                    unsafe { ((**self.0).AddGameFrameHook)(self.0, run_queued_tasks) };
+
+                    (self.0, Arc::new(Mutex::new(Some(Vec::new()))))
+                });
+
+                Dispatcher { tasks: tasks.clone() }
+            })
+        }
+    }
+
+    /// Queues closures from any thread to be run on the main thread, at the start of the next game frame.
+    ///
+    /// Once the extension is unloaded, tasks still queued are dropped without running and no more are accepted.
+    #[derive(Clone)]
+    pub struct Dispatcher {
+        tasks: TaskQueue,
+    }
+
+    impl std::fmt::Debug for Dispatcher {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("Dispatcher").finish_non_exhaustive()
+        }
+    }
+
+    impl Dispatcher {
+        pub fn dispatch<F: FnOnce() + Send + 'static>(&self, task: F) -> Result<(), &'static str> {
+            // A poisoned lock only means a thread panicked while pushing, the queue itself is still intact.
+            let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+
+            match &mut *tasks {
+                Some(tasks) => {
+                    tasks.push(Box::new(task));
+                    Ok(())
+                }
+                None => Err("Extension has been unloaded"),
+            }
+        }
+    }
+
+    unsafe extern "C" fn run_queued_tasks(simulating: bool) {
+        let tasks = DISPATCH_QUEUE.with(|queue| match &*queue.borrow() {
+            Some((_, tasks)) => tasks.lock().unwrap_or_else(|e| e.into_inner()).as_mut().map(std::mem::take),
+            None => None,
+        });
+
+        for task in tasks.into_iter().flatten() {
+            // It mustn't unwind into SourceMod.
+            if let Err(err) = catch_unwind(AssertUnwindSafe(task)) {
+                log::error!("Unexpected panic in queued task: {}", super::panic_message(&*err));
+            }
+        }
+    }
+
+    pub(crate) fn close_dispatch_queue() {
+        if let Some((sourcemod, tasks)) = DISPATCH_QUEUE.with(|queue| queue.borrow_mut().take()) {
+            unsafe { ((**sourcemod).RemoveGameFrameHook)(sourcemod, run_queued_tasks) };
+
+            let tasks = tasks.lock().unwrap_or_else(|e| e.into_inner()).take();
+            drop(tasks);
+        }
+    }
+}
+
+pub use IRootConsoleApi::*;
+mod IRootConsoleApi {
+    pub use super::vtables::IRootConsole;
+
+    use super::types::IRootConsolePtr;
+    use super::{RequestableInterface, SMInterface};
+    use c_str_macro::c_str;
+
+    impl RequestableInterface for IRootConsole {
+        fn get_interface_name() -> &'static str {
+            "IRootConsole"
+        }
+
+        fn get_interface_version() -> u32 {
+            2
+        }
+
+        unsafe fn from_raw_interface(iface: SMInterface) -> Self {
+            IRootConsole(iface.0 as IRootConsolePtr)
+        }
+    }
+
+    impl IRootConsole {
+        /// Prints a line to the server console.
+        pub fn console_print(&self, message: &str) {
+            let message = super::ISourceModApi::to_c_string(message);
+
+            unsafe { ((**self.0).ConsolePrint)(self.0, c_str!("%s").as_ptr(), message.as_ptr()) }
+        }
+    }
+}
+
+pub use IThreaderApi::*;
+mod IThreaderApi {
+    pub use super::vtables::{IEventSignal, IMutex, IThreader};
+
+    use super::types::{IThreadCreatorPtr, IThreadHandlePtr, IThreadPtr, IThreaderPtr, ThreadFlags};
+    use super::vtables::{IThreadCreatorVtable, IThreadVtable, IThreaderVtable};
+    use super::{Dispatcher, ISourceMod, RequestableInterface, SMInterface};
+    use sm_ext_derive::vtable_adapter;
+    use std::any::Any;
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::marker::PhantomData;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    impl RequestableInterface for IThreader {
+        fn get_interface_name() -> &'static str {
+            "IThreader"
+        }
+
+        fn get_interface_version() -> u32 {
+            3
+        }
+
+        unsafe fn from_raw_interface(iface: SMInterface) -> Self {
+            IThreader(iface.0 as IThreaderPtr)
+        }
+    }
+
+    // `class IThreader : public SMInterface, public IThreadCreator` in IThreader.h. Neither base class has any fields,
+    // so an IThreader is just their two vtable pointers, with IThreadCreator's second.
+    #[repr(C)]
+    struct IThreaderObject {
+        threader: *mut IThreaderVtable,
+        creator: *mut IThreadCreatorVtable,
+    }
+
+    const _: () = assert!(std::mem::offset_of!(IThreaderObject, creator) == std::mem::size_of::<*mut IThreaderVtable>());
+
+    fn thread_creator(threader: IThreaderPtr) -> IThreadCreatorPtr {
+        unsafe { std::ptr::addr_of_mut!((*(threader as *mut IThreaderObject)).creator) }
+    }
+
+    /// A mutex created by [`IThreader::make_mutex`], which is destroyed when this is dropped.
+    #[derive(Debug)]
+    pub struct OwnedMutex(IMutex);
+
+    // SourceMod's mutexes are made to be shared between threads.
+    unsafe impl Send for OwnedMutex {}
+    unsafe impl Sync for OwnedMutex {}
+
+    impl std::ops::Deref for OwnedMutex {
+        type Target = IMutex;
+
+        fn deref(&self) -> &IMutex {
+            &self.0
+        }
+    }
+
+    impl Drop for OwnedMutex {
+        fn drop(&mut self) {
+            unsafe { ((**(self.0).0).DestroyThis)((self.0).0) }
+        }
+    }
+
+    /// An event signal created by [`IThreader::make_event_signal`], which is destroyed when this is dropped.
+    #[derive(Debug)]
+    pub struct OwnedEventSignal(IEventSignal);
+
+    // SourceMod's event signals are made to be shared between threads.
+    unsafe impl Send for OwnedEventSignal {}
+    unsafe impl Sync for OwnedEventSignal {}
+
+    impl std::ops::Deref for OwnedEventSignal {
+        type Target = IEventSignal;
+
+        fn deref(&self) -> &IEventSignal {
+            &self.0
+        }
+    }
+
+    impl Drop for OwnedEventSignal {
+        fn drop(&mut self) {
+            unsafe { ((**(self.0).0).DestroyThis)((self.0).0) }
+        }
+    }
+
+    impl IMutex {
+        /// Blocks until the mutex is locked, it is unlocked when the guard is dropped.
+        pub fn lock(&self) -> IMutexGuard<'_> {
+            unsafe { ((**self.0).Lock)(self.0) };
+            IMutexGuard { mutex: self, _not_send: PhantomData }
+        }
+
+        /// Locks the mutex if it isn't already locked.
+        pub fn try_lock(&self) -> Option<IMutexGuard<'_>> {
+            if unsafe { ((**self.0).TryLock)(self.0) } {
+                Some(IMutexGuard { mutex: self, _not_send: PhantomData })
+            } else {
+                None
+            }
+        }
+    }
+
+    /// A lock on an [`IMutex`], see [`IMutex::lock`].
+    #[derive(Debug)]
+    pub struct IMutexGuard<'a> {
+        mutex: &'a IMutex,
+        // The mutex must be unlocked by the thread that locked it.
+        _not_send: PhantomData<*const ()>,
+    }
+
+    impl Drop for IMutexGuard<'_> {
+        fn drop(&mut self) {
+            unsafe { ((**self.mutex.0).Unlock)(self.mutex.0) }
+        }
+    }
+
+    type Job = Box<dyn FnOnce() + Send>;
+    type JobResult = Box<dyn Any + Send>;
+    type JobCallback = Box<dyn FnOnce(JobResult)>;
+
+    struct QueuedJob {
+        id: u64,
+        job: Job,
+        on_complete: JobCallback,
+    }
+
+    struct RunningJob {
+        handle: IThreadHandlePtr,
+        // Must outlive the thread, which runs the job through it.
+        _thread: Box<JobThread>,
+        on_complete: JobCallback,
+        pool: Rc<PoolState>,
+    }
+
+    struct PoolState {
+        threader: IThreaderPtr,
+        dispatcher: Dispatcher,
+        max_threads: usize,
+        running: Cell<usize>,
+        queued: RefCell<VecDeque<QueuedJob>>,
+    }
+
+    thread_local! {
+        // Jobs with a live thread, which are all joined when the extension is unloaded.
+        static RUNNING_JOBS: RefCell<Vec<(u64, RunningJob)>> = const { RefCell::new(Vec::new()) };
+        static NEXT_JOB_ID: Cell<u64> = const { Cell::new(1) };
+        static JOBS_STOPPED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    #[repr(C)]
+    struct JobThread {
+        vtable: *mut IThreadVtable,
+        job: Option<Job>,
+    }
+
+    impl Drop for JobThread {
+        fn drop(&mut self) {
+            unsafe {
+                drop(Box::from_raw(self.vtable));
+            }
+        }
+    }
+
+    #[vtable_adapter(IThreadVtable)]
+    impl JobThread {
+        // We own the thread object, SourceMod never deletes it.
+        fn destructor(&mut self) {}
+
+        #[cfg(unix)]
+        fn destructor2(&mut self) {}
+
+        fn run_thread(&mut self, handle: IThreadHandlePtr) {
+            if let Some(job) = self.job.take() {
+                job();
+            }
+        }
+
+        fn on_terminate(&mut self, handle: IThreadHandlePtr, cancel: bool) {}
+    }
+
+    impl IThreader {
+        /// Creates a mutex that can be shared with other extensions.
+        pub fn make_mutex(&self) -> Result<OwnedMutex, &'static str> {
+            let mutex = unsafe { ((**self.0).MakeMutex)(self.0) };
+
+            if mutex.is_null() {
+                return Err("Failed to create mutex");
+            }
+
+            Ok(OwnedMutex(IMutex(mutex)))
+        }
+
+        /// Creates an event signal that can be shared with other extensions.
+        pub fn make_event_signal(&self) -> Result<OwnedEventSignal, &'static str> {
+            let signal = unsafe { ((**self.0).MakeEventSignal)(self.0) };
+
+            if signal.is_null() {
+                return Err("Failed to create event signal");
+            }
+
+            Ok(OwnedEventSignal(IEventSignal(signal)))
+        }
+
+        /// Creates a pool that runs jobs on up to `max_threads` SourceMod threads at a time, see [`WorkerPool::submit`].
+        pub fn create_worker_pool(&self, sourcemod: &ISourceMod, max_threads: usize) -> WorkerPool {
+            let state = PoolState { threader: self.0, dispatcher: sourcemod.dispatcher(), max_threads: max_threads.max(1), running: Cell::new(0), queued: RefCell::new(VecDeque::new()) };
+
+            WorkerPool { state: Rc::new(state) }
+        }
+    }
+
+    /// Runs jobs on SourceMod threads, and delivers their results to callbacks on the main thread.
+    ///
+    /// All running jobs are joined when the extension is unloaded, without calling their callbacks.
+    pub struct WorkerPool {
+        state: Rc<PoolState>,
+    }
+
+    impl WorkerPool {
+        /// Queues `job` to be run on a worker thread, then `on_complete` to be called with its result on the main thread.
+        ///
+        /// If the job panics, the panic is logged and `on_complete` is never called.
+        /// Jobs stay queued when a thread can't be created for them, this only fails if no other job is running to start them later.
+        pub fn submit<T, F, C>(&self, job: F, on_complete: C) -> Result<(), &'static str>
+        where
+            T: Send + 'static,
+            F: FnOnce() -> T + Send + 'static,
+            C: FnOnce(T) + 'static,
+        {
+            if JOBS_STOPPED.with(|stopped| stopped.get()) {
+                return Err("Extension has been unloaded");
+            }
+
+            let id = NEXT_JOB_ID.with(|next| next.replace(next.get() + 1));
+            let dispatcher = self.state.dispatcher.clone();
+
+            let job: Job = Box::new(move || {
+                // It mustn't unwind into SourceMod.
+                let result = catch_unwind(AssertUnwindSafe(job)).map(|result| Box::new(result) as JobResult);
+                if let Err(err) = &result {
+                    log::error!("Unexpected panic in worker job, its callback won't be called: {}", super::panic_message(&**err));
+                }
+
+                // This fails once the extension is unloading, it is then waiting for this thread to finish instead.
+                let _ = dispatcher.dispatch(move || finish_job(id, result.ok()));
+            });
+
+            let on_complete: JobCallback = Box::new(move |result| {
+                if let Ok(result) = result.downcast::<T>() {
+                    on_complete(*result);
+                }
+            });
+
+            self.state.queued.borrow_mut().push_back(QueuedJob { id, job, on_complete });
+
+            // Queued jobs are retried when a running job finishes, but with none running this one would never start.
+            if !start_queued_jobs(&self.state) && self.state.running.get() == 0 {
+                self.state.queued.borrow_mut().retain(|queued| queued.id != id);
+                return Err("Failed to create thread");
+            }
+
+            Ok(())
+        }
+
+        /// Returns the number of jobs that are queued or running.
+        pub fn pending(&self) -> usize {
+            self.state.queued.borrow().len() + self.state.running.get()
+        }
+    }
+
+    // Starts queued jobs until the pool is full, returning false if a thread couldn't be created.
+    fn start_queued_jobs(pool: &Rc<PoolState>) -> bool {
+        while pool.running.get() < pool.max_threads {
+            let QueuedJob { id, job, on_complete } = match pool.queued.borrow_mut().pop_front() {
+                Some(queued) => queued,
+                None => break,
+            };
+
+            let mut thread = Box::new(JobThread { vtable: Box::into_raw(Box::new(JobThread::vtable())), job: Some(job) });
+
+            let creator = thread_creator(pool.threader);
+            let handle = unsafe { ((**creator).MakeThread)(creator, &mut *thread as *mut JobThread as IThreadPtr, ThreadFlags::DEFAULT) };
+
+            if handle.is_null() {
+                if let Some(job) = thread.job.take() {
+                    pool.queued.borrow_mut().push_front(QueuedJob { id, job, on_complete });
+                }
+                return false;
+            }
+
+            pool.running.set(pool.running.get() + 1);
+            RUNNING_JOBS.with(|jobs| jobs.borrow_mut().push((id, RunningJob { handle, _thread: thread, on_complete, pool: pool.clone() })));
+        }
+
+        true
+    }
+
+    fn join_job(job: &RunningJob) {
+        unsafe {
+            ((**job.handle).WaitForThread)(job.handle);
+            ((**job.handle).DestroyThis)(job.handle);
+        }
+    }
+
+    fn finish_job(id: u64, result: Option<JobResult>) {
+        let job = RUNNING_JOBS.with(|jobs| {
+            let mut jobs = jobs.borrow_mut();
+            let idx = jobs.iter().position(|&(entry, _)| entry == id)?;
+            Some(jobs.remove(idx).1)
+        });
+
+        let job = match job {
+            Some(job) => job,
+            None => return,
+        };
+
+        // The job has already finished, this just waits for its thread to exit.
+        join_job(&job);
+
+        let pool = job.pool;
+        pool.running.set(pool.running.get() - 1);
+
+        if let Some(result) = result {
+            (job.on_complete)(result);
+        }
+
+        start_queued_jobs(&pool);
+    }
+
+    pub(crate) fn join_all_jobs() {
+        JOBS_STOPPED.with(|stopped| stopped.set(true));
+
+        let jobs = RUNNING_JOBS.with(|jobs| jobs.borrow_mut().split_off(0));
+
+        for (_, job) in &jobs {
+            job.pool.queued.borrow_mut().clear();
+            join_job(job);
+        }
+    }
+}
+
+pub use ITimerSystemApi::*;
+mod ITimerSystemApi {
+    pub use super::vtables::ITimerSystem;
 
-    pub trait IExtensionInterface {
-        fn on_extension_load(&mut self, me: IExtension, sys: IShareSys, late: bool) -> Result<(), CString> {
-            Ok(())
+    use super::types::{ITimedEventPtr, ITimerPtr, ITimerSystemPtr, ResultType, TimerFlags};
+    use super::vtables::ITimedEventVtable;
+    use super::{RequestableInterface, SMInterface};
+    use sm_ext_derive::vtable_adapter;
+    use std::cell::{Cell, RefCell};
+    use std::os::raw::c_void;
+
+    impl RequestableInterface for ITimerSystem {
+        fn get_interface_name() -> &'static str {
+            "ITimerSys"
         }
-        fn on_extension_unload(&mut self) {}
-        fn on_extensions_all_loaded(&mut self) {}
-        fn on_extension_pause_change(&mut self, pause: bool) {}
-        fn on_core_map_start(&mut self, edict_list: *mut c_void, edict_count: i32, client_max: i32) {}
-        fn on_core_map_end(&mut self) {}
-        fn query_interface_drop(&mut self, interface: SMInterface) -> bool {
-            false
+
+        fn get_interface_version() -> u32 {
+            4
         }
-        fn notify_interface_drop(&mut self, interface: SMInterface) {}
-        fn query_running(&mut self) -> Result<(), CString> {
-            Ok(())
+
+        unsafe fn from_raw_interface(iface: SMInterface) -> Self {
+            ITimerSystem(iface.0 as ITimerSystemPtr)
         }
-        fn on_dependencies_dropped(&mut self) {}
     }
 
-    pub trait IExtensionMetadata {
-        fn get_extension_name(&self) -> &'static CStr;
-        fn get_extension_url(&self) -> &'static CStr;
-        fn get_extension_tag(&self) -> &'static CStr;
-        fn get_extension_author(&self) -> &'static CStr;
-        fn get_extension_ver_string(&self) -> &'static CStr;
-        fn get_extension_description(&self) -> &'static CStr;
-        fn get_extension_date_string(&self) -> &'static CStr;
+    thread_local! {
+        // Timers that haven't ended yet, so that they can be killed when the map or extension ends.
+        static TIMERS: RefCell<Vec<(u64, ITimerSystemPtr, ITimerPtr, TimerFlags)>> = const { RefCell::new(Vec::new()) };
+        static NEXT_TIMER_ID: Cell<u64> = const { Cell::new(1) };
+
+        // Shared by every timer, their callbacks are passed as the timer data instead.
+        static TIMED_EVENT: RefCell<Option<Box<TimedEvent>>> = const { RefCell::new(None) };
+
+        // Timers whose callbacks are currently running, innermost last.
+        static EXECUTING_TIMERS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct TimerCallback {
+        id: u64,
+        callback: RefCell<Box<dyn FnMut() -> ResultType>>,
     }
 
     #[repr(C)]
-    pub struct IExtensionInterfaceAdapter<T: IExtensionInterface + IExtensionMetadata> {
-        vtable: *mut IExtensionInterfaceVtable,
-        delegate: T,
+    struct TimedEvent {
+        vtable: *mut ITimedEventVtable,
     }
 
-    impl<T: IExtensionInterface + IExtensionMetadata> Drop for IExtensionInterfaceAdapter<T> {
+    impl Drop for TimedEvent {
         fn drop(&mut self) {
             unsafe {
                 drop(Box::from_raw(self.vtable));
@@ -351,299 +3907,602 @@ mod IExtensionInterfaceApi {
         }
     }
 
-    impl<T: IExtensionInterface + IExtensionMetadata> IExtensionInterfaceAdapter<T> {
-        pub fn new(delegate: T) -> IExtensionInterfaceAdapter<T> {
-            let vtable = IExtensionInterfaceVtable {
-                GetExtensionVersion: IExtensionInterfaceAdapter::<T>::get_extension_version,
-                OnExtensionLoad: IExtensionInterfaceAdapter::<T>::on_extension_load,
-                OnExtensionUnload: IExtensionInterfaceAdapter::<T>::on_extension_unload,
-                OnExtensionsAllLoaded: IExtensionInterfaceAdapter::<T>::on_extensions_all_loaded,
-                OnExtensionPauseChange: IExtensionInterfaceAdapter::<T>::on_extension_pause_change,
-                QueryInterfaceDrop: IExtensionInterfaceAdapter::<T>::query_interface_drop,
-                NotifyInterfaceDrop: IExtensionInterfaceAdapter::<T>::notify_interface_drop,
-                QueryRunning: IExtensionInterfaceAdapter::<T>::query_running,
-                IsMetamodExtension: IExtensionInterfaceAdapter::<T>::is_metamod_extension,
-                GetExtensionName: IExtensionInterfaceAdapter::<T>::get_extension_name,
-                GetExtensionURL: IExtensionInterfaceAdapter::<T>::get_extension_url,
-                GetExtensionTag: IExtensionInterfaceAdapter::<T>::get_extension_tag,
-                GetExtensionAuthor: IExtensionInterfaceAdapter::<T>::get_extension_author,
-                GetExtensionVerString: IExtensionInterfaceAdapter::<T>::get_extension_ver_string,
-                GetExtensionDescription: IExtensionInterfaceAdapter::<T>::get_extension_description,
-                GetExtensionDateString: IExtensionInterfaceAdapter::<T>::get_extension_date_string,
-                OnCoreMapStart: IExtensionInterfaceAdapter::<T>::on_core_map_start,
-                OnDependenciesDropped: IExtensionInterfaceAdapter::<T>::on_dependencies_dropped,
-                OnCoreMapEnd: IExtensionInterfaceAdapter::<T>::on_core_map_end,
+    #[vtable_adapter(ITimedEventVtable)]
+    impl TimedEvent {
+        fn on_timer(&mut self, timer: ITimerPtr, data: *mut c_void) -> ResultType {
+            // SourceMod doesn't end a timer while it is executing, so the callback stays alive until this returns.
+            let callback = unsafe { &*(data as *const TimerCallback) };
+
+            // The callback can't run inside of itself, which `Timer::fire_once` would otherwise do.
+            let mut function = match callback.callback.try_borrow_mut() {
+                Ok(function) => function,
+                Err(_) => return ResultType::CONTINUE,
             };
 
-            IExtensionInterfaceAdapter { vtable: Box::into_raw(Box::new(vtable)), delegate }
+            EXECUTING_TIMERS.with(|executing| executing.borrow_mut().push(callback.id));
+            let result = function();
+            EXECUTING_TIMERS.with(|executing| executing.borrow_mut().pop());
+
+            result
         }
 
-        unsafe extern "thiscall" fn get_extension_version(this: IExtensionInterfacePtr) -> i32 {
-            8
+        fn on_timer_end(&mut self, timer: ITimerPtr, data: *mut c_void) {
+            let callback = unsafe { Box::from_raw(data as *mut TimerCallback) };
+            TIMERS.with(|timers| timers.borrow_mut().retain(|&(id, _, _, _)| id != callback.id));
         }
+    }
 
-        unsafe extern "thiscall" fn on_extension_load(this: IExtensionInterfacePtr, me: IExtensionPtr, sys: IShareSysPtr, error: *mut c_char, maxlength: size_t, late: bool) -> bool {
-            match (*this.cast::<Self>()).delegate.on_extension_load(IExtension(me), IShareSys(sys), late) {
-                Ok(_) => true,
-                Err(str) => {
-                    libc::strncpy(error, str.as_ptr(), maxlength);
-                    false
-                }
+    impl ITimerSystem {
+        /// Creates a timer that calls `callback` after `interval` seconds.
+        ///
+        /// With [`TimerFlags::REPEAT`] the timer keeps calling it every `interval` seconds, until it returns [`ResultType::STOP`].
+        /// With [`TimerFlags::NO_MAPCHANGE`] the timer is killed when the map ends.
+        /// All timers are killed when the extension is unloaded.
+        pub fn create_timer<F>(&self, interval: f32, flags: TimerFlags, callback: F) -> Result<Timer, &'static str>
+        where
+            F: FnMut() -> ResultType + 'static,
+        {
+            let id = NEXT_TIMER_ID.with(|next| next.replace(next.get() + 1));
+            let data = Box::into_raw(Box::new(TimerCallback { id, callback: RefCell::new(Box::new(callback)) }));
+
+            let timer = TIMED_EVENT.with(|event| {
+                let mut event = event.borrow_mut();
+                let event = event.get_or_insert_with(|| Box::new(TimedEvent { vtable: Box::into_raw(Box::new(TimedEvent::vtable())) }));
+
+                unsafe { ((**self.0).CreateTimer)(self.0, &mut **event as *mut TimedEvent as ITimedEventPtr, interval, data as *mut c_void, flags) }
+            });
+
+            if timer.is_null() {
+                unsafe { drop(Box::from_raw(data)) };
+                return Err("Failed to create timer");
             }
-        }
 
-        unsafe extern "thiscall" fn on_extension_unload(this: IExtensionInterfacePtr) {
-            (*this.cast::<Self>()).delegate.on_extension_unload()
-        }
+            TIMERS.with(|timers| timers.borrow_mut().push((id, self.0, timer, flags)));
 
-        unsafe extern "thiscall" fn on_extensions_all_loaded(this: IExtensionInterfacePtr) {
-            (*this.cast::<Self>()).delegate.on_extensions_all_loaded()
+            Ok(Timer { id })
         }
+    }
 
-        unsafe extern "thiscall" fn on_extension_pause_change(this: IExtensionInterfacePtr, pause: bool) {
-            (*this.cast::<Self>()).delegate.on_extension_pause_change(pause)
-        }
+    /// A timer created with [`ITimerSystem::create_timer`].
+    ///
+    /// The timer is killed when this is dropped, unless it was detached.
+    #[derive(Debug)]
+    pub struct Timer {
+        id: u64,
+    }
 
-        unsafe extern "thiscall" fn query_interface_drop(this: IExtensionInterfacePtr, interface: SMInterfacePtr) -> bool {
-            (*this.cast::<Self>()).delegate.query_interface_drop(SMInterface(interface))
+    impl Timer {
+        /// Returns false once the timer has ended, either by running out or being killed.
+        pub fn is_running(&self) -> bool {
+            find_timer(self.id).is_some()
         }
 
-        unsafe extern "thiscall" fn notify_interface_drop(this: IExtensionInterfacePtr, interface: SMInterfacePtr) {
-            (*this.cast::<Self>()).delegate.notify_interface_drop(SMInterface(interface))
-        }
+        /// Calls the timer's callback now, as if its interval had elapsed.
+        ///
+        /// Returns false without calling it if the timer has ended, or if its callback is the one currently running.
+        pub fn fire_once(&self) -> bool {
+            if EXECUTING_TIMERS.with(|executing| executing.borrow().contains(&self.id)) {
+                return false;
+            }
 
-        unsafe extern "thiscall" fn query_running(this: IExtensionInterfacePtr, error: *mut c_char, maxlength: size_t) -> bool {
-            match (*this.cast::<Self>()).delegate.query_running() {
-                Ok(_) => true,
-                Err(str) => {
-                    libc::strncpy(error, str.as_ptr(), maxlength);
-                    false
+            match find_timer(self.id) {
+                Some((sys, timer)) => {
+                    unsafe { ((**sys).FireTimerOnce)(sys, timer, false) }
+                    true
                 }
+                None => false,
             }
         }
 
-        unsafe extern "thiscall" fn is_metamod_extension(this: IExtensionInterfacePtr) -> bool {
-            false
+        /// Lets the timer run until it ends by itself, or the map or extension ends.
+        pub fn detach(mut self) {
+            self.id = 0;
         }
+    }
 
-        unsafe extern "thiscall" fn get_extension_name(this: IExtensionInterfacePtr) -> *const c_char {
-            (*this.cast::<Self>()).delegate.get_extension_name().as_ptr()
+    impl Drop for Timer {
+        fn drop(&mut self) {
+            if let Some((sys, timer)) = find_timer(self.id) {
+                unsafe { ((**sys).KillTimer)(sys, timer) }
+            }
         }
+    }
 
-        unsafe extern "thiscall" fn get_extension_url(this: IExtensionInterfacePtr) -> *const c_char {
-            (*this.cast::<Self>()).delegate.get_extension_url().as_ptr()
-        }
+    fn find_timer(id: u64) -> Option<(ITimerSystemPtr, ITimerPtr)> {
+        TIMERS.with(|timers| timers.borrow().iter().find(|&&(entry, _, _, _)| entry == id).map(|&(_, sys, timer, _)| (sys, timer)))
+    }
 
-        unsafe extern "thiscall" fn get_extension_tag(this: IExtensionInterfacePtr) -> *const c_char {
-            (*this.cast::<Self>()).delegate.get_extension_tag().as_ptr()
-        }
+    fn kill_timers(filter: impl Fn(TimerFlags) -> bool) {
+        let timers: Vec<_> = TIMERS.with(|timers| timers.borrow().iter().filter(|&&(_, _, _, flags)| filter(flags)).copied().collect());
 
-        unsafe extern "thiscall" fn get_extension_author(this: IExtensionInterfacePtr) -> *const c_char {
-            (*this.cast::<Self>()).delegate.get_extension_author().as_ptr()
+        // Killing a timer ends it, which removes it from the list.
+        for (_, sys, timer, _) in timers.into_iter().rev() {
+            unsafe { ((**sys).KillTimer)(sys, timer) }
         }
+    }
 
-        unsafe extern "thiscall" fn get_extension_ver_string(this: IExtensionInterfacePtr) -> *const c_char {
-            (*this.cast::<Self>()).delegate.get_extension_ver_string().as_ptr()
-        }
+    pub(crate) fn kill_map_change_timers() {
+        kill_timers(|flags| flags.contains(TimerFlags::NO_MAPCHANGE));
+    }
 
-        unsafe extern "thiscall" fn get_extension_description(this: IExtensionInterfacePtr) -> *const c_char {
-            (*this.cast::<Self>()).delegate.get_extension_description().as_ptr()
-        }
+    pub(crate) fn kill_all_timers() {
+        kill_timers(|_| true);
+
+        TIMED_EVENT.with(|event| event.borrow_mut().take());
+    }
+}
+
+/// A single-threaded executor for futures, polled on the main thread every game frame.
+///
+/// It has to be started with [`executor::start`](start) before any tasks are spawned, and is stopped when the extension is unloaded.
+pub mod executor {
+    use super::types::{cell_t, IPluginContextPtr, IPluginPtr, ISourceModPtr, ITimerSystemPtr, TryFromWithContext};
+    use super::{CallArgs, IPluginContext, IPluginManager, ISourceMod, ITimerSystem, PluginFunction};
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::future::Future;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::pin::Pin;
+    use std::ptr::null_mut;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct Task {
+        // The plugin context the task belongs to, if any, so that it can be cancelled when the plugin is unloaded.
+        owner: IPluginContextPtr,
+        future: Pin<Box<dyn Future<Output = ()>>>,
+    }
+
+    struct Executor {
+        sourcemod: ISourceModPtr,
+        timers: ITimerSystemPtr,
+        next_id: u64,
+        tasks: HashMap<u64, Task>,
+        // Tasks that have been woken since they were last polled, wakers can be called from any thread.
+        woken: Arc<Mutex<Vec<u64>>>,
+        // The deadline and waker of each pending `Sleep`, by its id.
+        sleepers: HashMap<u64, (f32, Waker)>,
+    }
+
+    thread_local! {
+        static EXECUTOR: RefCell<Option<Executor>> = const { RefCell::new(None) };
+        static NEXT_SLEEP_ID: Cell<u64> = const { Cell::new(1) };
+    }
+
+    struct TaskWaker {
+        id: u64,
+        woken: Arc<Mutex<Vec<u64>>>,
+    }
 
-        unsafe extern "thiscall" fn get_extension_date_string(this: IExtensionInterfacePtr) -> *const c_char {
-            (*this.cast::<Self>()).delegate.get_extension_date_string().as_ptr()
+    impl Wake for TaskWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref()
         }
 
-        unsafe extern "thiscall" fn on_core_map_start(this: IExtensionInterfacePtr, edict_list: *mut c_void, edict_count: c_int, client_max: c_int) {
-            (*this.cast::<Self>()).delegate.on_core_map_start(edict_list, edict_count, client_max)
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.woken.lock().unwrap_or_else(|e| e.into_inner()).push(self.id);
         }
+    }
+
+    /// Starts polling spawned tasks every game frame.
+    ///
+    /// The timer system provides the game time for [`sleep`], and the plugin manager is used to cancel the tasks of unloaded plugins.
+    pub fn start(sourcemod: &ISourceMod, timers: &ITimerSystem, plugins: &IPluginManager) {
+        EXECUTOR.with(|executor| {
+            let mut executor = executor.borrow_mut();
+            if executor.is_some() {
+                return;
+            }
+
+            unsafe { ((**sourcemod.0).AddGameFrameHook)(sourcemod.0, poll_tasks) };
+            super::IPluginManagerApi::listen_for_unloaded_plugins(plugins);
+
+            *executor = Some(Executor { sourcemod: sourcemod.0, timers: timers.0, next_id: 1, tasks: HashMap::new(), woken: Arc::new(Mutex::new(Vec::new())), sleepers: HashMap::new() });
+        });
+    }
+
+    /// Spawns a future to be run on the main thread, it is first polled on the next game frame.
+    pub fn spawn_local<F: Future<Output = ()> + 'static>(future: F) -> Result<(), &'static str> {
+        spawn_task(Task { owner: null_mut(), future: Box::pin(future) })
+    }
+
+    fn spawn_task(task: Task) -> Result<(), &'static str> {
+        EXECUTOR.with(|executor| {
+            let mut executor = executor.borrow_mut();
+            let executor = executor.as_mut().ok_or("Executor is not running")?;
 
-        unsafe extern "thiscall" fn on_dependencies_dropped(this: IExtensionInterfacePtr) {
-            (*this.cast::<Self>()).delegate.on_dependencies_dropped()
+            let id = executor.next_id;
+            executor.next_id += 1;
+            executor.tasks.insert(id, task);
+            executor.woken.lock().unwrap_or_else(|e| e.into_inner()).push(id);
+
+            Ok(())
+        })
+    }
+
+    /// A task that calls a plugin function with the output of a future, which a native can return to spawn it.
+    ///
+    /// The task is cancelled if the plugin is unloaded before the future completes.
+    pub struct CallbackTask {
+        owner: IPluginContextPtr,
+        future: Pin<Box<dyn Future<Output = ()>>>,
+    }
+
+    impl CallbackTask {
+        pub fn new<F, A>(callback: PluginFunction, future: F) -> CallbackTask
+        where
+            F: Future<Output = A> + 'static,
+            A: for<'a> CallArgs<'a> + 'static,
+        {
+            let owner = callback.context();
+            let future = async move {
+                let args = future.await;
+                let _ = callback.call(args);
+            };
+
+            CallbackTask { owner, future: Box::pin(future) }
         }
 
-        unsafe extern "thiscall" fn on_core_map_end(this: IExtensionInterfacePtr) {
-            (*this.cast::<Self>()).delegate.on_core_map_end()
+        pub fn spawn(self) -> Result<(), &'static str> {
+            spawn_task(Task { owner: self.owner, future: self.future })
         }
     }
-}
 
-pub use IExtensionApi::*;
-mod IExtensionApi {
-    use super::types::{IExtensionInterfacePtr, IExtensionPtr, IdentityTokenPtr};
+    impl<'a> TryFromWithContext<'a, CallbackTask> for cell_t {
+        type Error = &'static str;
 
-    use std::ffi::CStr;
-    use std::os::raw::c_char;
-    use std::str::Utf8Error;
+        fn try_from_plugin(ctx: &'a IPluginContext, task: CallbackTask) -> Result<Self, Self::Error> {
+            task.spawn()?;
 
-    #[derive(Debug)]
-    pub enum IsRunningError<'a> {
-        WithReason(&'a str),
-        InvalidReason(Utf8Error),
+            Ok(0.into())
+        }
+    }
+
+    /// Returns a future that completes after `seconds` of game time have passed.
+    ///
+    /// The future must be polled by the executor, game time doesn't pass while the server is hibernating.
+    /// It completes straight away if the executor isn't running.
+    pub fn sleep(seconds: f32) -> Sleep {
+        let id = NEXT_SLEEP_ID.with(|next| next.replace(next.get() + 1));
+        Sleep { id, seconds, deadline: None }
     }
 
     #[derive(Debug)]
-    pub struct IExtension(pub IExtensionPtr);
+    pub struct Sleep {
+        id: u64,
+        seconds: f32,
+        deadline: Option<f32>,
+    }
 
-    impl IExtension {
-        pub fn is_loaded(&self) -> bool {
-            unsafe { ((**self.0).IsLoaded)(self.0) }
+    impl Future for Sleep {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            EXECUTOR.with(|executor| {
+                let mut executor = executor.borrow_mut();
+                let executor = match executor.as_mut() {
+                    Some(executor) => executor,
+                    None => return Poll::Ready(()),
+                };
+
+                let now = unsafe { ((**executor.timers).GetTickedTime)(executor.timers) };
+                let seconds = self.seconds;
+                let deadline = *self.deadline.get_or_insert(now + seconds);
+
+                if now >= deadline {
+                    executor.sleepers.remove(&self.id);
+                    return Poll::Ready(());
+                }
+
+                // Polling again only replaces the waker.
+                executor.sleepers.insert(self.id, (deadline, cx.waker().clone()));
+                Poll::Pending
+            })
         }
+    }
 
-        pub fn get_api(&self) -> IExtensionInterfacePtr {
-            unsafe { ((**self.0).GetAPI)(self.0) }
+    impl Drop for Sleep {
+        fn drop(&mut self) {
+            // Sleeps are dropped with their task, which can happen while the executor is being stopped.
+            let _ = EXECUTOR.try_with(|executor| {
+                if let Ok(mut executor) = executor.try_borrow_mut() {
+                    if let Some(executor) = executor.as_mut() {
+                        executor.sleepers.remove(&self.id);
+                    }
+                }
+            });
         }
+    }
 
-        pub fn get_filename(&self) -> Result<&str, Utf8Error> {
-            unsafe {
-                let c_name = ((**self.0).GetFilename)(self.0);
+    unsafe extern "C" fn poll_tasks(simulating: bool) {
+        let (woken, queue) = match EXECUTOR.with(|executor| {
+            let mut executor = executor.borrow_mut();
+            let executor = executor.as_mut()?;
 
-                CStr::from_ptr(c_name).to_str()
+            let now = ((**executor.timers).GetTickedTime)(executor.timers);
+            for (deadline, waker) in executor.sleepers.values() {
+                if now >= *deadline {
+                    waker.wake_by_ref();
+                }
             }
-        }
 
-        pub fn get_identity(&self) -> IdentityTokenPtr {
-            unsafe { ((**self.0).GetIdentity)(self.0) }
-        }
+            let mut woken = std::mem::take(&mut *executor.woken.lock().unwrap_or_else(|e| e.into_inner()));
+            woken.sort_unstable();
+            woken.dedup();
 
-        pub fn is_running(&self) -> Result<(), IsRunningError> {
-            unsafe {
-                let mut c_error = [0 as c_char; 256];
-                let result = ((**self.0).IsRunning)(self.0, c_error.as_mut_ptr(), c_error.len());
+            Some((woken, executor.woken.clone()))
+        }) {
+            Some(woken) => woken,
+            None => return,
+        };
 
-                if result {
-                    Ok(())
-                } else {
-                    match CStr::from_ptr(c_error.as_ptr()).to_str() {
-                        Ok(error) => Err(IsRunningError::WithReason(error)),
-                        Err(e) => Err(IsRunningError::InvalidReason(e)),
+        for id in woken {
+            // Taken out while it is polled, as it can spawn tasks or wake itself.
+            let task = EXECUTOR.with(|executor| executor.borrow_mut().as_mut().and_then(|executor| executor.tasks.remove(&id)));
+            let mut task = match task {
+                Some(task) => task,
+                None => continue,
+            };
+
+            let waker = Waker::from(Arc::new(TaskWaker { id, woken: queue.clone() }));
+            let mut cx = Context::from_waker(&waker);
+
+            // It mustn't unwind into SourceMod, a task that panics is dropped.
+            match catch_unwind(AssertUnwindSafe(|| task.future.as_mut().poll(&mut cx))) {
+                Ok(Poll::Pending) => EXECUTOR.with(|executor| {
+                    if let Some(executor) = executor.borrow_mut().as_mut() {
+                        executor.tasks.insert(id, task);
                     }
-                }
+                }),
+                Ok(Poll::Ready(())) => {}
+                Err(err) => log::error!("Unexpected panic in task: {}", super::panic_message(&*err)),
             }
         }
+    }
+
+    pub(crate) fn cancel_tasks_of_plugin(plugin: IPluginPtr) {
+        let context = unsafe { ((**plugin).GetBaseContext)(plugin) };
+
+        let cancelled: Vec<Task> = EXECUTOR.with(|executor| match executor.borrow_mut().as_mut() {
+            Some(executor) => {
+                let ids: Vec<u64> = executor.tasks.iter().filter(|(_, task)| task.owner == context).map(|(&id, _)| id).collect();
+                ids.iter().filter_map(|id| executor.tasks.remove(id)).collect()
+            }
+            None => Vec::new(),
+        });
 
-        pub fn is_external(&self) -> bool {
-            unsafe { ((**self.0).IsExternal)(self.0) }
+        // Dropped outside of the borrow, in case they spawn more tasks.
+        drop(cancelled);
+    }
+
+    pub(crate) fn stop() {
+        if let Some(executor) = EXECUTOR.with(|executor| executor.borrow_mut().take()) {
+            unsafe { ((**executor.sourcemod).RemoveGameFrameHook)(executor.sourcemod, poll_tasks) };
         }
     }
 }
 
-pub use SMInterfaceApi::*;
-mod SMInterfaceApi {
-    use super::types::SMInterfacePtr;
-
+/// A [`log`] backend that writes to SourceMod's logs.
+///
+/// Errors go to the SourceMod error log and warnings and info messages to the SourceMod log, both tagged with the extension's tag by SourceMod.
+/// Debug and trace messages are only printed to the server console, through SourceMod's root console.
+/// Libraries using `tracing` are included when its `log` feature is enabled.
+pub mod logger {
+    use super::types::{IExtensionInterfacePtr, IExtensionPtr, IRootConsolePtr, ISourceModPtr, PathType};
+    use super::{Dispatcher, IExtension, IRootConsole, ISourceMod};
+    use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
     use std::ffi::CStr;
-    use std::str::Utf8Error;
+    use std::fs::{File, OpenOptions};
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, ThreadId};
+    use std::time::{SystemTime, UNIX_EPOCH};
 
+    /// Configures the logger, which should be installed at the start of `on_extension_load`.
     #[derive(Debug)]
-    pub struct SMInterface(pub SMInterfacePtr);
+    pub struct LoggerBuilder {
+        sourcemod: ISourceModPtr,
+        console: IRootConsolePtr,
+        myself: IExtensionPtr,
+        level: LevelFilter,
+        log_file: bool,
+    }
 
-    impl SMInterface {
-        pub fn get_interface_version(&self) -> u32 {
-            unsafe { ((**self.0).GetInterfaceVersion)(self.0) }
+    impl LoggerBuilder {
+        pub fn new(sourcemod: &ISourceMod, console: &IRootConsole, myself: &IExtension) -> LoggerBuilder {
+            LoggerBuilder { sourcemod: sourcemod.0, console: console.0, myself: myself.0, level: LevelFilter::Info, log_file: false }
         }
 
-        pub fn get_interface_name(&self) -> Result<&str, Utf8Error> {
-            unsafe {
-                let c_name = ((**self.0).GetInterfaceName)(self.0);
+        /// Sets the most verbose level that is logged, defaults to [`LevelFilter::Info`].
+        pub fn level(mut self, level: LevelFilter) -> LoggerBuilder {
+            self.level = level;
+            self
+        }
 
-                CStr::from_ptr(c_name).to_str()
-            }
+        /// Also writes every message to the extension's own log under `logs/`, which starts a new file each day (UTC).
+        pub fn log_file(mut self, log_file: bool) -> LoggerBuilder {
+            self.log_file = log_file;
+            self
         }
 
-        pub fn is_version_compatible(&self, version: u32) -> bool {
-            unsafe { ((**self.0).IsVersionCompatible)(self.0, version) }
+        /// Installs the logger, this fails if a different logger has already been installed.
+        ///
+        /// The `log` crate's logger can't be replaced, so ours stays installed and only logs while the extension is loaded.
+        /// Installing it again, such as when the extension is reloaded, replaces its configuration.
+        pub fn install(self) -> Result<(), SetLoggerError> {
+            let tag = unsafe {
+                let api: IExtensionInterfacePtr = ((**self.myself).GetAPI)(self.myself);
+                CStr::from_ptr(((**api).GetExtensionTag)(api)).to_string_lossy().into_owned()
+            };
+
+            let sourcemod = ISourceMod(self.sourcemod);
+            let file = if self.log_file {
+                let prefix = sourcemod.build_path(PathType::SM, &format!("logs/{}_", tag.to_lowercase()));
+                Some(Arc::new(Mutex::new(LogFile { prefix, day: 0, file: None })))
+            } else {
+                None
+            };
+
+            if !INSTALLED.load(Ordering::Acquire) {
+                log::set_logger(&LOGGER)?;
+                INSTALLED.store(true, Ordering::Release);
+            }
+
+            let state = LoggerState { sourcemod: self.sourcemod, console: self.console, myself: self.myself, tag: tag.into(), main_thread: thread::current().id(), dispatcher: sourcemod.dispatcher(), file };
+            *STATE.lock().unwrap_or_else(|e| e.into_inner()) = Some(state);
+            log::set_max_level(self.level);
+
+            Ok(())
         }
     }
-}
 
-pub use IShareSysApi::*;
-mod IShareSysApi {
-    use super::types::{IShareSysPtr, NativeInfo, SMInterfacePtr};
-    use super::IExtensionApi::IExtension;
-    use super::SMInterfaceApi::SMInterface;
+    // Whether `LOGGER` is the `log` crate's logger.
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
 
-    use std::ffi::{CString, NulError};
-    use std::ptr::null_mut;
+    static LOGGER: Logger = Logger;
 
-    #[derive(Debug)]
-    pub enum RequestInterfaceError {
-        StringError(NulError),
-        InterfaceError(),
+    // Where `LOGGER` writes to, which is only set while the extension is loaded.
+    static STATE: Mutex<Option<LoggerState>> = Mutex::new(None);
+
+    pub(crate) fn uninstall() {
+        log::set_max_level(LevelFilter::Off);
+        STATE.lock().unwrap_or_else(|e| e.into_inner()).take();
     }
 
-    #[derive(Debug)]
-    pub struct IShareSys(pub IShareSysPtr);
+    struct LogFile {
+        prefix: String,
+        day: u64,
+        file: Option<File>,
+    }
 
-    impl IShareSys {
-        pub fn request_interface(&self, myself: &IExtension, name: &str, version: u32) -> Result<SMInterface, RequestInterfaceError> {
-            let c_name = CString::new(name).map_err(RequestInterfaceError::StringError)?;
+    impl LogFile {
+        fn write(&mut self, now: u64, line: &str) {
+            let day = now / 86400;
+            if self.file.is_none() || day != self.day {
+                let (year, month, date) = civil_from_days(day);
+                let path = format!("{}{:04}{:02}{:02}.log", self.prefix, year, month, date);
 
-            unsafe {
-                let mut iface: SMInterfacePtr = null_mut();
-                let res = ((**self.0).RequestInterface)(self.0, c_name.as_ptr(), version, myself.0, &mut iface);
+                self.day = day;
+                self.file = OpenOptions::new().create(true).append(true).open(path).ok();
+            }
 
-                if res {
-                    Ok(SMInterface(iface))
-                } else {
-                    Err(RequestInterfaceError::InterfaceError())
-                }
+            if let Some(file) = &mut self.file {
+                let (year, month, date) = civil_from_days(day);
+                let secs = now % 86400;
+                let _ = writeln!(file, "L {:02}/{:02}/{:04} - {:02}:{:02}:{:02}: {}", month, date, year, secs / 3600, secs / 60 % 60, secs % 60, line);
             }
         }
+    }
 
-        /// # Safety
-        ///
-        /// This is should be be used via the `register_natives!` macro only.
-        pub unsafe fn add_natives(&self, myself: &IExtension, natives: *const NativeInfo) {
-            ((**self.0).AddNatives)(self.0, myself.0, natives)
+    // Days since the Unix epoch to a (year, month, day) date, see http://howardhinnant.github.io/date_algorithms.html
+    fn civil_from_days(days: u64) -> (u64, u64, u64) {
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        (year, month, day)
+    }
+
+    #[derive(Clone)]
+    struct LoggerState {
+        sourcemod: ISourceModPtr,
+        console: IRootConsolePtr,
+        myself: IExtensionPtr,
+        tag: Arc<str>,
+        main_thread: ThreadId,
+        dispatcher: Dispatcher,
+        file: Option<Arc<Mutex<LogFile>>>,
+    }
+
+    // SourceMod is only called from the main thread, other threads queue their messages to it.
+    unsafe impl Send for LoggerState {}
+
+    impl LoggerState {
+        fn write(&self, level: Level, message: &str) {
+            let sourcemod = ISourceMod(self.sourcemod);
+            let myself = IExtension(self.myself);
+
+            match level {
+                Level::Error => sourcemod.log_error(&myself, message),
+                Level::Warn | Level::Info => sourcemod.log_message(&myself, message),
+                Level::Debug | Level::Trace => IRootConsole(self.console).console_print(&format!("[{}] {}", self.tag, message)),
+            }
+
+            if let Some(file) = &self.file {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                file.lock().unwrap_or_else(|e| e.into_inner()).write(now, &format!("[{}] {}: {}", self.tag, level, message));
+            }
         }
     }
-}
 
-pub use IPluginContextApi::*;
-mod IPluginContextApi {
-    use super::types::{cell_t, IPluginContextPtr};
-    use c_str_macro::c_str;
-    use std::ffi::{CStr, CString};
-    use std::os::raw::c_char;
-    use std::ptr::null_mut;
+    struct Logger;
 
-    #[derive(Debug)]
-    pub struct IPluginContext(pub IPluginContextPtr);
+    impl Logger {
+        // Copied out so that the lock isn't held while calling into SourceMod, which could log again.
+        fn state(&self) -> Option<LoggerState> {
+            STATE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+        }
+    }
 
-    impl IPluginContext {
-        pub fn throw_native_error(&self, err: String) -> cell_t {
-            let fmt = c_str!("%s");
-            let err = CString::new(err).unwrap_or_else(|_| c_str!("ThrowNativeError message contained NUL byte").into());
-            unsafe { ((**self.0).ThrowNativeError)(self.0, fmt.as_ptr(), err.as_ptr()) }
+    impl Log for Logger {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.level() <= log::max_level()
         }
 
-        pub fn local_to_phys_addr(&self, local: cell_t) -> Result<&mut cell_t, i32> {
-            unsafe {
-                let mut addr: *mut cell_t = null_mut();
-                let res = ((**self.0).LocalToPhysAddr)(self.0, local, &mut addr);
+        fn log(&self, record: &Record<'_>) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
 
-                if res == 0 {
-                    Ok(&mut *addr)
-                } else {
-                    Err(res)
-                }
+            let state = match self.state() {
+                Some(state) => state,
+                None => return,
+            };
+
+            let level = record.level();
+            let message = record.args().to_string();
+
+            if thread::current().id() == state.main_thread {
+                state.write(level, &message);
+                return;
             }
-        }
 
-        pub fn local_to_string(&self, local: cell_t) -> Result<&CStr, i32> {
-            unsafe {
-                let mut addr: *mut c_char = null_mut();
-                let res = ((**self.0).LocalToString)(self.0, local, &mut addr);
+            // Logged again once it's on the main thread, messages are dropped once the extension is unloading.
+            let _ = state.dispatcher.dispatch(move || log::log!(level, "{}", message));
+        }
 
-                if res == 0 {
-                    Ok(CStr::from_ptr(addr))
-                } else {
-                    Err(res)
+        fn flush(&self) {
+            if let Some(file) = self.state().and_then(|state| state.file) {
+                if let Some(file) = &mut file.lock().unwrap_or_else(|e| e.into_inner()).file {
+                    let _ = file.flush();
                 }
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn civil_from_days_converts_dates() {
+            assert_eq!(civil_from_days(0), (1970, 1, 1));
+            assert_eq!(civil_from_days(31), (1970, 2, 1));
+            assert_eq!(civil_from_days(365), (1971, 1, 1));
+            assert_eq!(civil_from_days(10957), (2000, 1, 1));
+            assert_eq!(civil_from_days(11016), (2000, 2, 29));
+            assert_eq!(civil_from_days(11017), (2000, 3, 1));
+            assert_eq!(civil_from_days(19782), (2024, 2, 29));
+            assert_eq!(civil_from_days(20453), (2025, 12, 31));
+        }
+    }
 }
 
 // TODO: Not a huge fan of this one, but it seems to be the most user-friendly option without requiring the new macro features in rust nightly.
@@ -653,12 +4512,13 @@ mod IPluginContextApi {
 macro_rules! declare_native {
     (fn $name:ident($ctx:ident: &IPluginContext, $args:ident: &[cell_t]) -> cell_t $body:tt) => {
         unsafe extern "C" fn $name(ctx: $crate::types::IPluginContextPtr, args: *const $crate::types::cell_t) -> $crate::types::cell_t {
-            use ::std::convert::TryInto;
+            use ::std::convert::TryFrom;
 
             let callback = |$ctx: &$crate::IPluginContext, $args: &[$crate::types::cell_t]| -> $crate::types::cell_t { $body };
 
-            let count: i32 = (*args).into();
-            let args = ::std::slice::from_raw_parts(args.offset(1), count.try_into().unwrap());
+            // The argument count is always a 32-bit cell, it needs widening to index the pointer-sized argument array.
+            let count = usize::try_from(i32::from(*args)).unwrap_or(0);
+            let args = ::std::slice::from_raw_parts(args.add(1), count);
             callback(&$crate::IPluginContext(ctx), &args)
         }
     };
@@ -691,26 +4551,25 @@ macro_rules! register_natives {
 }
 
 pub fn safe_native_invoke<F: FnOnce() -> Result<types::cell_t, Box<dyn ::std::error::Error>> + std::panic::UnwindSafe>(ctx: &IPluginContext, f: F) -> types::cell_t {
+    types::begin_native_call();
     let result = std::panic::catch_unwind(f);
+    types::end_native_call();
 
     match result {
         Ok(result) => match result {
             Ok(result) => result,
             Err(err) => ctx.throw_native_error(err.to_string()),
         },
-        Err(err) => {
-            let msg = format!(
-                "Unexpected panic: {}",
-                if let Some(str_slice) = err.downcast_ref::<&'static str>() {
-                    str_slice
-                } else if let Some(string) = err.downcast_ref::<String>() {
-                    string
-                } else {
-                    "Unknown message"
-                }
-            );
+        Err(err) => ctx.throw_native_error(format!("Unexpected panic: {}", panic_message(&*err))),
+    }
+}
 
-            ctx.throw_native_error(msg)
-        }
+pub(crate) fn panic_message(err: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(str_slice) = err.downcast_ref::<&'static str>() {
+        str_slice
+    } else if let Some(string) = err.downcast_ref::<String>() {
+        string
+    } else {
+        "Unknown message"
     }
 }